@@ -0,0 +1,56 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::png::{Png, PngRef};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+}
+
+// A typical small image: header, a few ancillary chunks and a single IDAT
+fn small_png() -> Vec<u8> {
+    Png::from_chunks(vec![
+        chunk("IHDR", vec![0, 0, 0, 16, 0, 0, 0, 16, 8, 6, 0, 0, 0]),
+        chunk("sRGB", vec![0]),
+        chunk("gAMA", vec![0, 0, 177, 143]),
+        chunk("IDAT", vec![0x5a; 1024]),
+        chunk("ruSt", b"I am the secret".to_vec()),
+        chunk("IEND", vec![]),
+    ])
+    .as_bytes()
+}
+
+// Image data split over many IDAT chunks the way encoders commonly write it
+fn large_png() -> Vec<u8> {
+    let mut chunks = vec![chunk("IHDR", vec![0, 0, 16, 0, 0, 0, 16, 0, 8, 6, 0, 0, 0])];
+    chunks.extend((0..256).map(|_| chunk("IDAT", vec![0x5a; 32 * 1024])));
+    chunks.push(chunk("IEND", vec![]));
+
+    Png::from_chunks(chunks).as_bytes()
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let small = small_png();
+    let large = large_png();
+
+    c.bench_function("parse small", |b| b.iter(|| Png::try_from(black_box(&small[..])).unwrap()));
+    c.bench_function("parse large", |b| b.iter(|| Png::try_from(black_box(&large[..])).unwrap()));
+    c.bench_function("parse large borrowed", |b| b.iter(|| PngRef::try_from(black_box(&large[..])).unwrap()));
+}
+
+fn bench_round_trip(c: &mut Criterion) {
+    let small = small_png();
+    let large = large_png();
+
+    c.bench_function("round trip small", |b| {
+        b.iter(|| Png::try_from(black_box(&small[..])).unwrap().as_bytes())
+    });
+    c.bench_function("round trip large", |b| {
+        b.iter(|| Png::try_from(black_box(&large[..])).unwrap().as_bytes())
+    });
+}
+
+criterion_group!(benches, bench_parse, bench_round_trip);
+criterion_main!(benches);
@@ -1,14 +1,489 @@
 extern crate clap;
-use clap::{App, AppSettings, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, Shell, SubCommand};
+
+use std::str::FromStr;
 
 use crate::*;
+use crate::png::CrcPolicy;
 
 pub fn process_args() {
-    let args = App::new("PNGme")
+    let args = build_app(false).get_matches();
+
+    commands::set_json_errors(args.is_present("json-errors"));
+    commands::set_preserve_mtime(args.is_present("preserve-mtime"));
+    commands::set_verify_writes(!args.is_present("no-verify"));
+
+    let read_options = commands::ReadOptions {
+        crc_policy: CrcPolicy::from_str(args.value_of("crc-policy").unwrap()).unwrap(),
+        base64: args.is_present("base64"),
+        assume_raw: args.is_present("assume-raw"),
+        #[cfg(feature = "network")]
+        max_download: match args.value_of("max-download") {
+            Some(max_download) => max_download.parse().unwrap(),
+            None => commands::DEFAULT_MAX_DOWNLOAD,
+        },
+        #[cfg(feature = "network")]
+        download_timeout: commands::DEFAULT_DOWNLOAD_TIMEOUT,
+    };
+
+    match args.subcommand_name() {
+        Some("encode") => {
+            let sub_args = args.subcommand_matches("encode").unwrap();
+
+            let mut options = commands::EncodeOptions::default();
+
+            if let Some(max_chunk_size) = sub_args.value_of("max-chunk-size") {
+                options.max_chunk_size = max_chunk_size.parse().unwrap();
+            }
+
+            options.no_overwrite = sub_args.is_present("no-overwrite");
+            options.framed = sub_args.is_present("framed");
+            options.strict_ascii = sub_args.is_present("strict-ascii");
+            options.upsert = sub_args.is_present("upsert");
+            options.concat = sub_args.is_present("concat");
+            options.pad_to = sub_args.value_of("pad-to").map(|len| len.parse().unwrap());
+            options.safe_to_copy = sub_args.is_present("safe-to-copy");
+            options.max_message_size = sub_args.value_of("max-message-size").map(|max| max.parse().unwrap());
+
+            // --type-auto and --message-env each leave out a positional, so the rest move up
+            let mut positionals = ["CHUNK_TYPE", "MESSAGE", "OUTPUT_FILE"]
+                .iter()
+                .filter_map(|name| sub_args.value_of(name));
+
+            let chunk_type = if sub_args.is_present("type-auto") {
+                None
+            } else {
+                positionals.next()
+            };
+
+            let msg = match sub_args.value_of("message-env") {
+                Some(name) => commands::message_from_env(name),
+                None => match positionals.next() {
+                    Some(msg) => msg.to_string(),
+                    None => usage_error("MESSAGE is required", clap::ErrorKind::MissingRequiredArgument),
+                },
+            };
+
+            let output_file = positionals.next();
+
+            if positionals.next().is_some() {
+                usage_error("Too many arguments, expected an optional OUTPUT_FILE after the message", clap::ErrorKind::TooManyValues);
+            }
+
+            let (suffix, in_dir) = (sub_args.value_of("suffix"), sub_args.value_of("in-dir"));
+
+            let derived_output_file = if suffix.is_some() || in_dir.is_some() {
+                if output_file.is_some() {
+                    usage_error("OUTPUT_FILE can't be combined with --suffix or --in-dir", clap::ErrorKind::ArgumentConflict);
+                }
+
+                Some(commands::derive_output_path(sub_args.value_of("FILE").unwrap(), suffix, in_dir))
+            } else {
+                None
+            };
+
+            commands::encode(
+                sub_args.value_of("FILE").unwrap(),
+                chunk_type,
+                &msg,
+                // optional, defaults to FILE
+                match (&derived_output_file, output_file) {
+                    (Some(f), _) => f,
+                    (None, Some(f)) => f,
+                    _ => sub_args.value_of("FILE").unwrap(),
+                },
+                &options,
+                &read_options,
+            );
+        }
+        Some("decode") => {
+            let sub_args = args.subcommand_matches("decode").unwrap();
+
+            if sub_args.is_present("all-types") {
+                commands::decode_all_types(
+                    sub_args.value_of("FILE").unwrap(),
+                    sub_args.is_present("count-only"),
+                    &read_options,
+                );
+            } else if let Some(keyword) = sub_args.value_of("keyword") {
+                commands::decode_keyword(sub_args.value_of("FILE").unwrap(), keyword, &read_options);
+            } else if let Some(offset) = sub_args.value_of("at-offset") {
+                commands::decode_at_offset(
+                    sub_args.value_of("FILE").unwrap(),
+                    offset.parse().unwrap(),
+                    sub_args.value_of("CHUNK_TYPE"),
+                );
+            } else if sub_args.is_present("recurse") {
+                commands::decode_recursive(
+                    sub_args.value_of("FILE").unwrap(),
+                    sub_args.value_of("CHUNK_TYPE").unwrap(),
+                    match sub_args.value_of("max-depth") {
+                        Some(depth) => depth.parse().unwrap(),
+                        None => commands::DEFAULT_MAX_DEPTH,
+                    },
+                    &read_options,
+                );
+            } else if cfg!(feature = "watch") && sub_args.is_present("watch") {
+                #[cfg(feature = "watch")]
+                commands::decode_watch(
+                    sub_args.value_of("FILE").unwrap(),
+                    sub_args.value_of("CHUNK_TYPE").unwrap(),
+                    sub_args.is_present("framed"),
+                    &read_options,
+                );
+            } else {
+                commands::decode(
+                    sub_args.value_of("FILE").unwrap(),
+                    sub_args.value_of("CHUNK_TYPE").unwrap(),
+                    sub_args.is_present("framed"),
+                    sub_args.is_present("json"),
+                    sub_args.is_present("trim-nulls"),
+                    &read_options,
+                );
+            }
+        }
+        Some("remove") => {
+            let sub_args = args.subcommand_matches("remove").unwrap();
+            let trailing_to_file = sub_args.value_of("trailing-to-file");
+
+            match sub_args.value_of("index") {
+                _ if sub_args.is_present("crc") => commands::remove_by_crc(
+                    sub_args.value_of("FILE").unwrap(),
+                    parse_crc(sub_args.value_of("crc").unwrap()).unwrap(),
+                    sub_args.value_of("CHUNK_TYPE"),
+                    trailing_to_file,
+                    &read_options,
+                ),
+                Some(index) => commands::remove_at(
+                    sub_args.value_of("FILE").unwrap(),
+                    index.parse().unwrap(),
+                    sub_args.is_present("force"),
+                    trailing_to_file,
+                    &read_options,
+                ),
+                None if sub_args.is_present("keep") || sub_args.is_present("max-remove") => commands::remove_bounded(
+                    sub_args.value_of("FILE").unwrap(),
+                    sub_args.value_of("CHUNK_TYPE").unwrap(),
+                    sub_args.value_of("keep").map_or(0, |n| n.parse().unwrap()),
+                    sub_args.value_of("max-remove").map(|n| n.parse().unwrap()),
+                    sub_args.is_present("quiet"),
+                    trailing_to_file,
+                    &read_options,
+                ),
+                None if sub_args.is_present("all") => commands::remove_all(
+                    sub_args.value_of("FILE").unwrap(),
+                    sub_args.value_of("CHUNK_TYPE").unwrap(),
+                    sub_args.is_present("quiet"),
+                    trailing_to_file,
+                    &read_options,
+                ),
+                None => commands::remove(
+                    sub_args.value_of("FILE").unwrap(),
+                    sub_args.value_of("CHUNK_TYPE").unwrap(),
+                    trailing_to_file,
+                    &read_options,
+                ),
+            }
+        }
+        Some("remove-unknown") => {
+            let sub_args = args.subcommand_matches("remove-unknown").unwrap();
+
+            commands::remove_unknown(
+                sub_args.value_of("FILE").unwrap(),
+                sub_args.is_present("quiet"),
+                sub_args.value_of("trailing-to-file"),
+                &read_options,
+            );
+        }
+        Some("print") => {
+            let sub_args = args.subcommand_matches("print").unwrap();
+
+            commands::print(
+                &sub_args.values_of("FILE").unwrap().collect::<Vec<_>>(),
+                sub_args.is_present("order"),
+                sub_args.is_present("grouped"),
+                row_limit(sub_args),
+                sub_args.is_present("summary-only"),
+                &read_options,
+            );
+        }
+        Some("fix-bits") => {
+            let sub_args = args.subcommand_matches("fix-bits").unwrap();
+
+            commands::fix_bits(
+                sub_args.value_of("FILE").unwrap(),
+                sub_args.value_of("CHUNK_TYPE").unwrap(),
+                sub_args.is_present("ancillary"),
+                sub_args.is_present("private"),
+                sub_args.is_present("safe-to-copy"),
+                &read_options,
+            );
+        }
+        Some("find") => {
+            let sub_args = args.subcommand_matches("find").unwrap();
+
+            commands::find(
+                sub_args.value_of("FILE").unwrap(),
+                sub_args.value_of("PATTERN").unwrap(),
+                sub_args.is_present("hex"),
+                sub_args.is_present("count-only"),
+                &read_options,
+            );
+        }
+        Some("append-trailing") => {
+            let sub_args = args.subcommand_matches("append-trailing").unwrap();
+
+            commands::append_trailing(
+                sub_args.value_of("FILE").unwrap(),
+                sub_args.value_of("data-file").unwrap(),
+                &read_options,
+            );
+        }
+        Some("extract-trailing") => {
+            let sub_args = args.subcommand_matches("extract-trailing").unwrap();
+
+            commands::extract_trailing(
+                sub_args.value_of("FILE").unwrap(),
+                sub_args.value_of("data-file"),
+                &read_options,
+            );
+        }
+        Some("extract-all") => {
+            let sub_args = args.subcommand_matches("extract-all").unwrap();
+
+            commands::extract_all(
+                sub_args.value_of("FILE").unwrap(),
+                sub_args.value_of("output-dir").unwrap(),
+                sub_args.is_present("by-type"),
+                &read_options,
+            );
+        }
+        Some("datauri") => {
+            let sub_args = args.subcommand_matches("datauri").unwrap();
+
+            commands::datauri(
+                sub_args.value_of("FILE").unwrap(),
+                sub_args.is_present("no-prefix"),
+                &read_options,
+            );
+        }
+        Some("verify") => {
+            let sub_args = args.subcommand_matches("verify").unwrap();
+
+            commands::verify(
+                &sub_args.values_of("FILE").unwrap().collect::<Vec<_>>(),
+                sub_args.is_present("deep"),
+                sub_args.is_present("fail-fast"),
+                sub_args.is_present("progress-json"),
+                &read_options,
+            );
+        }
+        Some("minify") => {
+            let sub_args = args.subcommand_matches("minify").unwrap();
+
+            commands::minify(
+                sub_args.value_of("FILE").unwrap(),
+                // optional, defaults to FILE
+                match sub_args.value_of("OUTPUT_FILE") {
+                    Some(f) => f,
+                    _ => sub_args.value_of("FILE").unwrap(),
+                },
+                sub_args.value_of("trailing-to-file"),
+                &read_options,
+            );
+        }
+        Some("set-text") => {
+            let sub_args = args.subcommand_matches("set-text").unwrap();
+
+            commands::set_text(
+                sub_args.value_of("FILE").unwrap(),
+                sub_args.value_of("KEYWORD").unwrap(),
+                sub_args.value_of("TEXT").unwrap(),
+                &read_options,
+            );
+        }
+        Some("strip-apng") => {
+            let sub_args = args.subcommand_matches("strip-apng").unwrap();
+
+            commands::strip_apng(
+                sub_args.value_of("FILE").unwrap(),
+                // optional, defaults to FILE
+                match sub_args.value_of("OUTPUT_FILE") {
+                    Some(f) => f,
+                    _ => sub_args.value_of("FILE").unwrap(),
+                },
+                &read_options,
+            );
+        }
+        Some("selftest") => commands::selftest(),
+        Some("list") => {
+            let sub_args = args.subcommand_matches("list").unwrap();
+
+            commands::list(
+                sub_args.value_of("FILE").unwrap(),
+                commands::OutputFormat::from_str(sub_args.value_of("format").unwrap()).unwrap(),
+                &commands::TypeFilter {
+                    only: sub_args.value_of("only-types").map(parse_type_list),
+                    exclude: sub_args.value_of("exclude-types").map(parse_type_list).unwrap_or_default(),
+                },
+                row_limit(sub_args),
+                &read_options,
+            );
+        }
+        Some("count") => {
+            let sub_args = args.subcommand_matches("count").unwrap();
+
+            commands::count(
+                sub_args.value_of("FILE").unwrap(),
+                commands::OutputFormat::from_str(sub_args.value_of("format").unwrap()).unwrap(),
+                &read_options,
+            );
+        }
+        Some("completions") => {
+            let sub_args = args.subcommand_matches("completions").unwrap();
+            let shell = sub_args.value_of("SHELL").unwrap().parse::<Shell>().unwrap();
+
+            build_app(true).gen_completions_to("pngme", shell, &mut std::io::stdout());
+        }
+        Some("info") => {
+            let sub_args = args.subcommand_matches("info").unwrap();
+
+            commands::info(
+                &sub_args.values_of("FILE").unwrap().collect::<Vec<_>>(),
+                sub_args.is_present("summary-only"),
+                &read_options,
+            );
+        }
+        #[cfg(feature = "checksum")]
+        Some("checksum") => {
+            let sub_args = args.subcommand_matches("checksum").unwrap();
+
+            commands::checksum(
+                sub_args.value_of("FILE").unwrap(),
+                commands::ChecksumAlgorithm::from_str(sub_args.value_of("algo").unwrap()).unwrap(),
+                sub_args.is_present("chunks"),
+                &read_options,
+            );
+        }
+        _ => panic!("unknown subcommand"),
+    }
+}
+
+/*
+With `completion_hints`, CHUNK_TYPE arguments list common chunk types. This is only
+used to generate completions, so that parsing still accepts any chunk type
+*/
+fn build_app(completion_hints: bool) -> App<'static, 'static> {
+    let decode = SubCommand::with_name("decode")
+        .about("Decodes (reads) a message from a PNG file")
+        .arg(Arg::with_name("FILE")
+            .help("PNG file name")
+            .required(true)
+            .index(1)
+        )
+        .arg(hint_chunk_types(completion_hints, Arg::with_name("CHUNK_TYPE")
+            .help("Chunk type")
+            .required_unless_one(&["all-types", "at-offset", "keyword"])
+            .index(2)
+        ))
+        .arg(Arg::with_name("all-types")
+            .long("all-types")
+            .help("Prints every ancillary chunk instead of a single chunk type")
+            .conflicts_with("CHUNK_TYPE")
+        )
+        .arg(count_only_arg()
+            .requires("all-types")
+        )
+        .arg(Arg::with_name("framed")
+            .long("framed")
+            .help("Reads a message written with `encode --framed`, failing for any other chunk data")
+            .conflicts_with("all-types")
+        )
+        .arg(Arg::with_name("at-offset")
+            .long("at-offset")
+            .help("Reads a single chunk starting at this byte offset, ignoring the rest of the file. CHUNK_TYPE is then optional.")
+            .takes_value(true)
+            .value_name("OFFSET")
+            .validator(is_usize)
+            .conflicts_with_all(&["all-types", "framed"])
+        )
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Prints the chunk type, length, crc and data (as UTF-8 and base64) as a JSON object")
+            .conflicts_with_all(&["all-types", "at-offset", "framed"])
+        )
+        .arg(Arg::with_name("keyword")
+            .long("keyword")
+            .help("Prints the text of the tEXt, zTXt or iTXt chunk with this keyword, instead of a chunk type")
+            .takes_value(true)
+            .value_name("KEYWORD")
+            .conflicts_with_all(&["CHUNK_TYPE", "all-types", "at-offset", "framed", "json"])
+        )
+        .arg(Arg::with_name("trim-nulls")
+            .long("trim-nulls")
+            .help("Drops trailing zero bytes from the message, such as the padding from `encode --pad-to`")
+            .conflicts_with_all(&["all-types", "at-offset", "json"])
+        )
+        .arg(Arg::with_name("recurse")
+            .long("recurse")
+            .help("Lists the chunks of a PNG stored in the chunk data, and of any PNGs inside those")
+            .conflicts_with_all(&["all-types", "at-offset", "framed", "json", "trim-nulls", "keyword"])
+        )
+        .arg(Arg::with_name("max-depth")
+            .long("max-depth")
+            .help("How many levels of nested PNGs --recurse lists [default: 4]")
+            .takes_value(true)
+            .value_name("DEPTH")
+            .validator(is_usize)
+            .requires("recurse")
+        );
+
+    #[cfg(feature = "watch")]
+    let decode = decode
+        .arg(Arg::with_name("watch")
+            .long("watch")
+            .help("Decodes again whenever the file changes, until interrupted")
+            .conflicts_with_all(&["all-types", "at-offset", "json", "recurse", "trim-nulls", "keyword"])
+        );
+
+    let app = App::new("PNGme")
         .version("1.0")
         .author("Paul Otten <lightnica@yahoo.com>")
         .about("Hides secret messages in PNG files")
         .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(Arg::with_name("crc-policy")
+            .long("crc-policy")
+            .help("Which chunks must have a valid crc when reading a PNG")
+            .takes_value(true)
+            .possible_values(&["all", "critical-only", "none"])
+            .default_value("all")
+            .global(true)
+        )
+        .arg(Arg::with_name("base64")
+            .long("base64")
+            .help("Reads input PNG files as base64. Data URIs are detected without it.")
+            .global(true)
+        )
+        .arg(Arg::with_name("assume-raw")
+            .long("assume-raw")
+            .help("Reads input without a PNG signature as a bare chunk stream. The signature is added back on write.")
+            .global(true)
+        )
+        .arg(Arg::with_name("preserve-mtime")
+            .long("preserve-mtime")
+            .help("Keeps the modification time of files that are overwritten")
+            .global(true)
+        )
+        .arg(Arg::with_name("no-verify")
+            .long("no-verify")
+            .help("Skips parsing the output of encode and remove back before writing it")
+            .global(true)
+        )
+        .arg(Arg::with_name("json-errors")
+            .long("json-errors")
+            .help("Prints failures to stderr as a JSON object with `kind`, `message` and context fields")
+            .global(true)
+        )
         .subcommand(SubCommand::with_name("encode")
             .about("Encodes (adds) a message to PNG file")
             .arg(Arg::with_name("FILE")
@@ -16,93 +491,587 @@ pub fn process_args() {
                 .required(true)
                 .index(1)
             )
-            .arg(Arg::with_name("CHUNK_TYPE")
+            .arg(hint_chunk_types(completion_hints, Arg::with_name("CHUNK_TYPE")
                 .help("Chunk type for message. Try \"RuSt\".")
-                .required(true)
+                .required_unless_all(&["type-auto", "message-env"])
                 .index(2)
-            )
+            ))
             .arg(Arg::with_name("MESSAGE")
                 .help("The message")
-                .required(true)
+                .required_unless_one(&["type-auto", "message-env"])
                 .index(3)
             )
             .arg(Arg::with_name("OUTPUT_FILE")
                 .help("[Optional] output png file name. Will default to overwriting FILE if not specified.")
                 .index(4)
             )
+            .arg(Arg::with_name("type-auto")
+                .long("type-auto")
+                .help("Picks the first unused chunk type of msGe, msAe, msBe ... msZe and prints it. The message is then given in place of CHUNK_TYPE.")
+            )
+            .arg(Arg::with_name("message-env")
+                .long("message-env")
+                .help("Reads the message from this environment variable. MESSAGE is then left out.")
+                .takes_value(true)
+                .value_name("VAR")
+            )
+            .arg(Arg::with_name("suffix")
+                .long("suffix")
+                .help("Writes to FILE with this added before the extension, e.g. `.stego` gives `cat.stego.png`")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("in-dir")
+                .long("in-dir")
+                .help("Writes to a file with the same name in this directory")
+                .takes_value(true)
+                .value_name("DIR")
+            )
+            .arg(Arg::with_name("no-overwrite")
+                .long("no-overwrite")
+                .help("Fails instead of replacing an existing output file")
+            )
+            .arg(Arg::with_name("framed")
+                .long("framed")
+                .help("Stores the message after a `PME1` header and its length, read back with `decode --framed`")
+            )
+            .arg(Arg::with_name("upsert")
+                .long("upsert")
+                .help("Removes every existing chunk of CHUNK_TYPE before adding the message, in the same write")
+            )
+            .arg(Arg::with_name("concat")
+                .long("concat")
+                .help("Appends the message to the existing chunk of CHUNK_TYPE, adding the chunk if there's none")
+                .conflicts_with_all(&["upsert", "framed"])
+            )
+            .arg(Arg::with_name("pad-to")
+                .long("pad-to")
+                .help("Pads the chunk data with zero bytes to exactly N bytes, failing if the message is longer")
+                .takes_value(true)
+                .value_name("N")
+                .validator(is_usize)
+                .conflicts_with("concat")
+            )
+            .arg(Arg::with_name("safe-to-copy")
+                .long("safe-to-copy")
+                .help("Sets the safe-to-copy bit of CHUNK_TYPE, so image editors keep the chunk when saving")
+            )
+            .arg(Arg::with_name("strict-ascii")
+                .long("strict-ascii")
+                .help("Fails if a message for a tEXt, zTXt or iTXt chunk has anything but printable ASCII and newlines")
+            )
+            .arg(Arg::with_name("max-chunk-size")
+                .long("max-chunk-size")
+                .help("Warns when the new chunk is larger than this many bytes [default: 10 MB]")
+                .takes_value(true)
+                .validator(is_usize)
+            )
+            .arg(Arg::with_name("max-message-size")
+                .long("max-message-size")
+                .help("Fails when the new chunk's data would be larger than this many bytes")
+                .takes_value(true)
+                .value_name("BYTES")
+                .validator(is_usize)
+            )
+        )
+        .subcommand(decode)
+        .subcommand(SubCommand::with_name("remove")
+            .about("Removed a message from a PNG file")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file name")
+                .required(true)
+                .index(1)
+            )
+            .arg(hint_chunk_types(completion_hints, Arg::with_name("CHUNK_TYPE")
+                .help("Chunk type, only needed with --crc when it should also match")
+                .required_unless_one(&["index", "crc"])
+                .index(2)
+            ))
+            .arg(Arg::with_name("index")
+                .long("index")
+                .help("Removes the chunk at this position instead of by chunk type")
+                .takes_value(true)
+                .validator(is_usize)
+                .conflicts_with("CHUNK_TYPE")
+            )
+            .arg(Arg::with_name("force")
+                .long("force")
+                .help("Allows --index to remove IHDR and IEND chunks")
+                .requires("index")
+            )
+            .arg(Arg::with_name("all")
+                .long("all")
+                .help("Removes every chunk of CHUNK_TYPE and reports what was removed")
+                .requires("CHUNK_TYPE")
+            )
+            .arg(Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Doesn't report the removed chunks with --all, --keep or --max-remove")
+            )
+            .arg(Arg::with_name("crc")
+                .long("crc")
+                .help("Removes the first chunk with this stored crc in hex, e.g. 0xabcd1234")
+                .takes_value(true)
+                .validator(is_crc)
+                .conflicts_with_all(&["index", "all"])
+            )
+            .arg(Arg::with_name("keep")
+                .long("keep")
+                .help("Removes every chunk of CHUNK_TYPE except the first N")
+                .takes_value(true)
+                .value_name("N")
+                .validator(is_usize)
+                .requires("CHUNK_TYPE")
+                .conflicts_with_all(&["index", "all", "crc"])
+            )
+            .arg(Arg::with_name("max-remove")
+                .long("max-remove")
+                .help("Removes at most N chunks of CHUNK_TYPE, from the first one (after any kept by --keep)")
+                .takes_value(true)
+                .value_name("N")
+                .validator(is_usize)
+                .requires("CHUNK_TYPE")
+                .conflicts_with_all(&["index", "all", "crc"])
+            )
+            .arg(trailing_to_file_arg())
+        )
+        .subcommand(SubCommand::with_name("remove-unknown")
+            .about("Removes every chunk whose type isn't defined by the PNG specification, e.g. private ones")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file name")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Doesn't report the removed chunks")
+            )
+            .arg(trailing_to_file_arg())
+        )
+        .subcommand(SubCommand::with_name("print")
+            .about("Prints information about PNG files")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file names")
+                .required(true)
+                .multiple(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("order")
+                .long("order")
+                .help("Marks chunks that are out of the order required by the PNG specification")
+            )
+            .arg(Arg::with_name("grouped")
+                .long("grouped")
+                .help("Collapses consecutive chunks of the same type into one line")
+                .conflicts_with("order")
+            )
+            .arg(limit_arg())
+            .arg(tail_arg())
+            .arg(summary_only_arg().conflicts_with_all(&["order", "grouped", "limit", "tail"]))
         )
-        .subcommand(SubCommand::with_name("decode")
-            .about("Decodes (reads) a message from a PNG file")
+        .subcommand(SubCommand::with_name("fix-bits")
+            .about("Clears the reserved bit of a chunk type and optionally sets its property bits")
             .arg(Arg::with_name("FILE")
                 .help("PNG file name")
                 .required(true)
                 .index(1)
             )
-            .arg(Arg::with_name("CHUNK_TYPE")
+            .arg(hint_chunk_types(completion_hints, Arg::with_name("CHUNK_TYPE")
                 .help("Chunk type")
                 .required(true)
                 .index(2)
+            ))
+            .arg(Arg::with_name("ancillary")
+                .long("ancillary")
+                .help("Marks the chunk as ancillary")
+            )
+            .arg(Arg::with_name("private")
+                .long("private")
+                .help("Marks the chunk as private")
+            )
+            .arg(Arg::with_name("safe-to-copy")
+                .long("safe-to-copy")
+                .help("Marks the chunk as safe to copy")
             )
         )
-        .subcommand(SubCommand::with_name("remove")
-            .about("Removed a message from a PNG file")
+        .subcommand(SubCommand::with_name("find")
+            .about("Finds which chunks contain a pattern")
             .arg(Arg::with_name("FILE")
                 .help("PNG file name")
                 .required(true)
                 .index(1)
             )
-            .arg(Arg::with_name("CHUNK_TYPE")
-                .help("Chunk type")
+            .arg(Arg::with_name("PATTERN")
+                .help("Text to search chunk data for")
                 .required(true)
                 .index(2)
             )
+            .arg(Arg::with_name("hex")
+                .long("hex")
+                .help("Treats PATTERN as hex encoded bytes, e.g. \"ca fe\"")
+            )
+            .arg(count_only_arg())
         )
-        .subcommand(SubCommand::with_name("print")
-            .about("Prints information about a PNG file")
+        .subcommand(SubCommand::with_name("append-trailing")
+            .about("Appends raw bytes after the IEND chunk of a PNG file")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file name")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("data-file")
+                .long("data-file")
+                .help("File containing the bytes to append")
+                .takes_value(true)
+                .required(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("extract-trailing")
+            .about("Extracts the raw bytes after the IEND chunk of a PNG file")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file name")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("data-file")
+                .long("data-file")
+                .help("[Optional] file to write the bytes to. Will default to stdout if not specified.")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("extract-all")
+            .about("Writes the data of every chunk to its own file, named after its index and type")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file name")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("output-dir")
+                .long("output-dir")
+                .help("Directory for the extracted files, created if needed")
+                .takes_value(true)
+                .value_name("DIR")
+                .required(true)
+            )
+            .arg(Arg::with_name("by-type")
+                .long("by-type")
+                .help("Writes each chunk as <type>/<index>.bin instead of <index>_<type>.bin")
+            )
+        )
+        .subcommand(SubCommand::with_name("datauri")
+            .about("Prints a PNG file as a base64 data URI")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file name")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("no-prefix")
+                .long("no-prefix")
+                .help("Prints only the base64 without the `data:image/png;base64,` prefix")
+            )
+        )
+        .subcommand(SubCommand::with_name("verify")
+            .about("Checks that PNG files are well formed")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file names, failures are listed after every file has been checked")
+                .required(true)
+                .multiple(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("deep")
+                .long("deep")
+                .help("Also checks that the compressed image data decompresses")
+            )
+            .arg(Arg::with_name("fail-fast")
+                .long("fail-fast")
+                .help("Stops at the first file that fails")
+            )
+            .arg(Arg::with_name("progress-json")
+                .long("progress-json")
+                .help("Writes a JSON line to stderr after each file, with `file`, `status` (ok or error), `index` and `total`")
+            )
+        )
+        .subcommand(SubCommand::with_name("minify")
+            .about("Drops every chunk that isn't needed to display the image")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file name")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("OUTPUT_FILE")
+                .help("[Optional] output png file name. Will default to overwriting FILE if not specified.")
+                .index(2)
+            )
+            .arg(trailing_to_file_arg())
+        )
+        .subcommand(SubCommand::with_name("set-text")
+            .about("Adds a tEXt chunk, replacing any with the same keyword")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file name")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("KEYWORD")
+                .help("Keyword, e.g. \"Comment\" or \"Author\"")
+                .required(true)
+                .index(2)
+            )
+            .arg(Arg::with_name("TEXT")
+                .help("Latin-1 text")
+                .required(true)
+                .index(3)
+            )
+        )
+        .subcommand(SubCommand::with_name("strip-apng")
+            .about("Removes the animation chunks of an APNG, leaving a static PNG of its default image")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file name")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("OUTPUT_FILE")
+                .help("[Optional] output png file name. Will default to overwriting FILE if not specified.")
+                .index(2)
+            )
+        )
+        .subcommand(SubCommand::with_name("selftest")
+            .about("Encodes and decodes a message in a temporary PNG, printing PASS or FAIL")
+            .setting(AppSettings::Hidden)
+        )
+        .subcommand(SubCommand::with_name("list")
+            .about("Lists every chunk in a PNG file")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file name")
+                .required(true)
+                .index(1)
+            )
+            .arg(format_arg())
+            .arg(Arg::with_name("only-types")
+                .long("only-types")
+                .help("Lists only chunks of these types, comma separated, e.g. tEXt,ruSt")
+                .takes_value(true)
+                .value_name("TYPES")
+                .validator(is_type_list)
+            )
+            .arg(Arg::with_name("exclude-types")
+                .long("exclude-types")
+                .help("Leaves out chunks of these types, comma separated, e.g. IDAT")
+                .takes_value(true)
+                .value_name("TYPES")
+                .validator(is_type_list)
+            )
+            .arg(limit_arg())
+            .arg(tail_arg())
+        )
+        .subcommand(SubCommand::with_name("count")
+            .about("Counts the chunks of each type in a PNG file")
             .arg(Arg::with_name("FILE")
                 .help("PNG file name")
                 .required(true)
                 .index(1)
             )
+            .arg(format_arg())
         )
-        .get_matches();
+        .subcommand(SubCommand::with_name("completions")
+            .about("Prints a shell completion script")
+            .arg(Arg::with_name("SHELL")
+                .help("Shell to generate completions for")
+                .required(true)
+                .possible_values(&Shell::variants())
+                .index(1)
+            )
+        )
+        .subcommand(SubCommand::with_name("info")
+            .about("Interprets well known ancillary chunks in PNG files")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file names")
+                .required(true)
+                .multiple(true)
+                .index(1)
+            )
+            .arg(summary_only_arg())
+        );
 
-    match args.subcommand_name() {
-        Some("encode") => {
-            let sub_args = args.subcommand_matches("encode").unwrap();
+    #[cfg(feature = "checksum")]
+    let app = app
+        .subcommand(SubCommand::with_name("checksum")
+            .about("Prints a hash of a PNG file as pngme writes it, for tracking changes")
+            .arg(Arg::with_name("FILE")
+                .help("PNG file name")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("algo")
+                .long("algo")
+                .help("Hash algorithm")
+                .takes_value(true)
+                .possible_values(&["sha256"])
+                .default_value("sha256")
+            )
+            .arg(Arg::with_name("chunks")
+                .long("chunks")
+                .help("Hashes each chunk's data instead, printing its index, type and digest")
+            )
+        );
 
-            commands::encode(
-                sub_args.value_of("FILE").unwrap(),
-                sub_args.value_of("CHUNK_TYPE").unwrap(),
-                sub_args.value_of("MESSAGE").unwrap(),
-                // optional, defaults to FILE
-                match sub_args.value_of("OUTPUT_FILE") {
-                    Some(f) => f,
-                    _ => sub_args.value_of("FILE").unwrap(),
-                },
-            );
-        }
-        Some("decode") => {
-            let sub_args = args.subcommand_matches("decode").unwrap();
+    #[cfg(feature = "network")]
+    let app = app
+        .arg(Arg::with_name("max-download")
+            .long("max-download")
+            .help("Fails when an input URL returns more than this many bytes [default: 50 MB]")
+            .takes_value(true)
+            .validator(is_usize)
+            .global(true)
+        );
 
-            commands::decode(
-                sub_args.value_of("FILE").unwrap(),
-                sub_args.value_of("CHUNK_TYPE").unwrap(),
-            );
-        }
-        Some("remove") => {
-            let sub_args = args.subcommand_matches("remove").unwrap();
+    app
+}
 
-            commands::remove(
-                sub_args.value_of("FILE").unwrap(),
-                sub_args.value_of("CHUNK_TYPE").unwrap(),
-            );
-        }
-        Some("print") => {
-            let sub_args = args.subcommand_matches("print").unwrap();
+fn format_arg() -> Arg<'static, 'static> {
+    Arg::with_name("format")
+        .long("format")
+        .help("Output format")
+        .takes_value(true)
+        .possible_values(&["text", "csv"])
+        .default_value("text")
+}
 
-            commands::print(sub_args.value_of("FILE").unwrap());
-        }
-        _ => panic!("unknown subcommand"),
+// Registered chunk types that commonly hold text, plus the type this tool suggests
+const COMMON_CHUNK_TYPES: [&str; 8] = ["tEXt", "zTXt", "iTXt", "eXIf", "tIME", "gAMA", "pHYs", "ruSt"];
+
+fn limit_arg() -> Arg<'static, 'static> {
+    Arg::with_name("limit")
+        .long("limit")
+        .help("Shows only the first N chunks")
+        .takes_value(true)
+        .value_name("N")
+        .validator(is_usize)
+}
+
+fn tail_arg() -> Arg<'static, 'static> {
+    Arg::with_name("tail")
+        .long("tail")
+        .help("Shows only the last N chunks")
+        .takes_value(true)
+        .value_name("N")
+        .validator(is_usize)
+        .conflicts_with("limit")
+}
+
+fn row_limit(sub_args: &clap::ArgMatches) -> commands::RowLimit {
+    match (sub_args.value_of("limit"), sub_args.value_of("tail")) {
+        (Some(n), _) => commands::RowLimit::First(n.parse().unwrap()),
+        (_, Some(n)) => commands::RowLimit::Last(n.parse().unwrap()),
+        _ => commands::RowLimit::All,
+    }
+}
+
+fn trailing_to_file_arg() -> Arg<'static, 'static> {
+    Arg::with_name("trailing-to-file")
+        .long("trailing-to-file")
+        .help("Saves any bytes after IEND to this file and drops them from the PNG")
+        .takes_value(true)
+        .value_name("PATH")
+}
+
+fn summary_only_arg() -> Arg<'static, 'static> {
+    Arg::with_name("summary-only")
+        .long("summary-only")
+        .help("Prints only the totals over all files: files, chunks, chunks of each type and bytes")
+}
+
+fn count_only_arg() -> Arg<'static, 'static> {
+    Arg::with_name("count-only")
+        .long("count-only")
+        .help("Prints only the number of matches, failing when there are none")
+}
+
+fn hint_chunk_types(completion_hints: bool, arg: Arg<'static, 'static>) -> Arg<'static, 'static> {
+    if completion_hints {
+        arg.possible_values(&COMMON_CHUNK_TYPES)
+    } else {
+        arg
+    }
+}
+
+fn usage_error(message: &str, kind: clap::ErrorKind) -> ! {
+    clap::Error::with_description(message, kind).exit()
+}
+
+fn is_usize(v: String) -> std::result::Result<(), String> {
+    match v.parse::<usize>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("`{}` is not a valid non-negative number", v)),
+    }
+}
+
+// Hex, with or without a leading `0x`, the way `print` shows crcs
+fn parse_crc(v: &str) -> Option<u32> {
+    let digits = v.strip_prefix("0x").or_else(|| v.strip_prefix("0X")).unwrap_or(v);
+    u32::from_str_radix(digits, 16).ok()
+}
+
+fn is_crc(v: String) -> std::result::Result<(), String> {
+    match parse_crc(&v) {
+        Some(_) => Ok(()),
+        None => Err(format!("`{}` is not a valid hex crc", v)),
+    }
+}
+
+fn parse_type_list(v: &str) -> Vec<String> {
+    v.split(',').map(|t| t.trim().to_string()).collect()
+}
+
+fn is_type_list(v: String) -> std::result::Result<(), String> {
+    match parse_type_list(&v).iter().find(|t| chunk_type::ChunkType::from_str(t).is_err()) {
+        Some(t) => Err(format!("`{}` is not a valid chunk type", t)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completions(shell: Shell) -> String {
+        let mut buffer = Vec::new();
+        build_app(true).gen_completions_to("pngme", shell, &mut buffer);
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn test_completions_hint_chunk_types() {
+        // the other shells' generators in clap don't list values for positional arguments
+        let script = completions(Shell::Zsh);
+        let hint = format!(":CHUNK_TYPE -- Chunk type:({})", COMMON_CHUNK_TYPES.join(" "));
+
+        assert!(script.contains(&hint));
+    }
+
+    #[test]
+    fn test_chunk_type_not_restricted_to_hints() {
+        let args = build_app(false).get_matches_from_safe(vec!["pngme", "decode", "file.png", "abCd"]).unwrap();
+        let sub_args = args.subcommand_matches("decode").unwrap();
+
+        assert_eq!(sub_args.value_of("CHUNK_TYPE"), Some("abCd"));
+    }
+
+    #[test]
+    fn test_parse_type_list() {
+        assert_eq!(parse_type_list("tEXt,ruSt"), vec!["tEXt", "ruSt"]);
+        assert_eq!(parse_type_list("IDAT"), vec!["IDAT"]);
+        assert_eq!(parse_type_list("tEXt, ruSt"), vec!["tEXt", "ruSt"]);
+        assert!(is_type_list("tEXt,ruSt".to_string()).is_ok());
+        assert!(is_type_list("tEXt,".to_string()).is_err());
+        assert!(is_type_list("tEXt,ru5t".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_parse_crc() {
+        assert_eq!(parse_crc("0xabcd1234"), Some(0xabcd1234));
+        assert_eq!(parse_crc("ABCD1234"), Some(0xabcd1234));
+        assert_eq!(parse_crc("0x1ffffffff"), None);
+        assert_eq!(parse_crc("crc"), None);
     }
 }
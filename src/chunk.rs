@@ -1,8 +1,40 @@
 use crate::chunk_type::ChunkType;
 use std::convert::TryFrom;
-use crate::{Error, Result};
+use crate::{zlib, Error, Result};
+
+// Parse error for a chunk whose crc only fits its type with the reserved bit cleared
+pub const RESERVED_BIT_CRC_HINT: &str =
+    "invalid crc, but it matches if the reserved bit is cleared, the chunk type may have been altered";
+
+/*
+Checksum used for chunk crcs
+
+The PNG spec mandates IEEE, the other polynomials are only available with
+the `experimental-crc` feature for interop testing against non-standard tools
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CrcAlgorithm {
+    #[default]
+    Ieee,
+    #[cfg(feature = "experimental-crc")]
+    Castagnoli,
+    #[cfg(feature = "experimental-crc")]
+    Koopman,
+}
+
+impl CrcAlgorithm {
+    pub fn checksum(&self, bytes: &[u8]) -> u32 {
+        match self {
+            CrcAlgorithm::Ieee => crc::crc32::checksum_ieee(bytes),
+            #[cfg(feature = "experimental-crc")]
+            CrcAlgorithm::Castagnoli => crc::crc32::checksum_castagnoli(bytes),
+            #[cfg(feature = "experimental-crc")]
+            CrcAlgorithm::Koopman => crc::crc32::checksum_koopman(bytes),
+        }
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Chunk {
     length: u32,
     chunk_type: ChunkType,
@@ -12,12 +44,64 @@ pub struct Chunk {
 
 impl Chunk {
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
-        let crc = Chunk::calc_crc(&chunk_type, &data);
+        Chunk::new_with_crc_algorithm(chunk_type, data, CrcAlgorithm::default())
+    }
+
+    // For callers that limit what they store, errors when `data` is longer than `max` bytes
+    pub fn new_bounded(chunk_type: ChunkType, data: Vec<u8>, max: usize) -> Result<Chunk> {
+        if data.len() > max {
+            return Err("data is larger than the maximum message size");
+        }
+
+        Ok(Chunk::new(chunk_type, data))
+    }
+
+    // The message is stored as its UTF-8 bytes
+    pub fn from_type_and_str(type_str: &str, msg: &str) -> Result<Chunk> {
+        let chunk_type: ChunkType = type_str.parse()?;
+
+        Ok(Chunk::new(chunk_type, msg.as_bytes().to_vec()))
+    }
+
+    /*
+    Builds a `tEXt` chunk, the reverse of `keyword_and_text`
+
+    The keyword must be 1 to 79 printable Latin-1 characters without leading,
+    trailing or consecutive spaces, and the text any Latin-1 without nulls
+    */
+    pub fn text(keyword: &str, value: &str) -> Result<Chunk> {
+        let keyword = match Chunk::string_to_latin1(keyword) {
+            Some(keyword) => keyword,
+            None => return Err("keyword must be Latin-1"),
+        };
+
+        if keyword.is_empty() || keyword.len() > 79 {
+            return Err("keyword must be 1 to 79 characters");
+        }
+
+        let printable = keyword.iter().all(|&b| (32..=126).contains(&b) || b >= 161);
+
+        if !printable || keyword.starts_with(b" ") || keyword.ends_with(b" ") || keyword.windows(2).any(|w| w == b"  ") {
+            return Err("keyword must be printable without leading, trailing or consecutive spaces");
+        }
+
+        let value = match Chunk::string_to_latin1(value) {
+            Some(value) if !value.contains(&0) => value,
+            _ => return Err("text must be Latin-1 without null characters"),
+        };
+
+        let data = [&keyword[..], &[0], &value[..]].concat();
+
+        Ok(Chunk::new(ChunkType::try_from(*b"tEXt")?, data))
+    }
+
+    pub fn new_with_crc_algorithm(chunk_type: ChunkType, data: Vec<u8>, algorithm: CrcAlgorithm) -> Chunk {
+        let crc = Chunk::calc_crc(&chunk_type, &data, algorithm);
         Chunk {
             length: data.len() as u32,
-            chunk_type: chunk_type,
-            data: data,
-            crc: crc,
+            chunk_type,
+            data,
+            crc,
         }
     }
 
@@ -29,14 +113,53 @@ impl Chunk {
         &self.chunk_type
     }
 
+    // The crc covers the chunk type, so it's recomputed
+    pub fn set_chunk_type(&mut self, chunk_type: ChunkType) {
+        self.crc = Chunk::calc_crc(&chunk_type, &self.data, CrcAlgorithm::default());
+        self.chunk_type = chunk_type;
+    }
+
     pub fn data(&self) -> &[u8] {
         &self.data
     }
 
+    /*
+    Leaves the length and crc stale, call `recompute_crc` when done editing,
+    or `sync_length` alone to keep the old crc on purpose
+    */
+    pub fn data_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+
+    pub fn set_data(&mut self, data: Vec<u8>) {
+        self.data = data;
+        self.recompute_crc();
+    }
+
+    pub fn append_data(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+        self.recompute_crc();
+    }
+
+    // Brings the length and crc back in line with the current type and data
+    pub fn recompute_crc(&mut self) {
+        self.sync_length();
+        self.crc = Chunk::calc_crc(&self.chunk_type, &self.data, CrcAlgorithm::default());
+    }
+
+    pub fn sync_length(&mut self) {
+        self.length = self.data.len() as u32;
+    }
+
     pub fn crc(&self) -> u32 {
         self.crc
     }
 
+    // Size on disk: length (4 bytes) + type (4 bytes) + data + crc (4 bytes)
+    pub fn total_len(&self) -> usize {
+        12 + self.data.len()
+    }
+
     pub fn data_as_string(&self) -> Result<String> {
         let mut s = String::new();
 
@@ -47,8 +170,76 @@ impl Chunk {
         Ok(s)
     }
 
+    // The data up to the first null byte, or all of it when there's none, as UTF-8
+    pub fn data_as_cstring(&self) -> Result<String> {
+        let end = self.data.iter().position(|b| *b == 0).unwrap_or(self.data.len());
+
+        match std::str::from_utf8(&self.data[..end]) {
+            Ok(s) => Ok(s.to_string()),
+            Err(_) => Err("string is not valid UTF-8"),
+        }
+    }
+
+    // What follows the string read by `data_as_cstring`, empty when there's no null byte
+    pub fn data_after_cstring(&self) -> &[u8] {
+        match Chunk::split_at_null(&self.data) {
+            Ok((_, rest)) => rest,
+            Err(_) => &[],
+        }
+    }
+
+    /*
+    Splits a `tEXt`, `zTXt` or `iTXt` chunk into its keyword and text,
+    decompressing the text where needed
+
+    Errors for any other chunk type
+    */
+    pub fn keyword_and_text(&self) -> Result<(String, String)> {
+        let (keyword, rest) = Chunk::split_at_null(&self.data)?;
+        let keyword = Chunk::latin1_to_string(keyword);
+
+        let text = match &*self.chunk_type.as_str() {
+            "tEXt" => Chunk::latin1_to_string(rest),
+            "zTXt" => {
+                let (method, compressed) = match rest.split_first() {
+                    Some((method, compressed)) => (*method, compressed),
+                    None => return Err("missing zTXt compression method"),
+                };
+
+                if method != 0 {
+                    return Err("unsupported compression method");
+                }
+
+                Chunk::latin1_to_string(&zlib::inflate(compressed)?)
+            }
+            "iTXt" => {
+                if rest.len() < 2 {
+                    return Err("missing iTXt compression fields");
+                }
+
+                let (compression_flag, method) = (rest[0], rest[1]);
+                let (_language_tag, rest) = Chunk::split_at_null(&rest[2..])?;
+                let (_translated_keyword, text) = Chunk::split_at_null(rest)?;
+
+                let text = match (compression_flag, method) {
+                    (0, _) => text.to_vec(),
+                    (1, 0) => zlib::inflate(text)?,
+                    _ => return Err("unsupported compression method"),
+                };
+
+                match String::from_utf8(text) {
+                    Ok(s) => s,
+                    Err(_) => return Err("iTXt text is not valid UTF-8"),
+                }
+            }
+            _ => return Err("not a text chunk"),
+        };
+
+        Ok((keyword, text))
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = vec![];
+        let mut bytes: Vec<u8> = Vec::with_capacity(self.total_len());
 
         // length
         bytes.extend(self.length.to_be_bytes().iter());
@@ -62,9 +253,56 @@ impl Chunk {
         // crc
         bytes.extend(self.crc.to_be_bytes().iter());
 
+        debug_assert!(Chunk::serialized_matches(&bytes, self.length, self.crc));
+
         bytes
     }
 
+    // Reads the length and crc back out of `as_bytes`, a wrong byte order would otherwise go unnoticed
+    pub fn verify_serialization(&self) -> bool {
+        Chunk::serialized_matches(&self.as_bytes(), self.length, self.crc)
+    }
+
+    // Compares type and data only, so a stale or corrupted crc doesn't matter
+    pub fn content_eq(&self, other: &Chunk) -> bool {
+        self.chunk_type == other.chunk_type && self.data == other.data
+    }
+
+    pub fn has_valid_crc(&self) -> bool {
+        self.crc_matches(self.crc)
+    }
+
+    pub fn has_valid_crc_with(&self, algorithm: CrcAlgorithm) -> bool {
+        Chunk::calc_crc(&self.chunk_type, &self.data, algorithm) == self.crc
+    }
+
+    // Checks the type and data against a crc from elsewhere, rather than the stored one
+    pub fn crc_matches(&self, expected: u32) -> bool {
+        Chunk::compute_crc(&self.chunk_type, &self.data) == expected
+    }
+
+    // The crc the PNG spec gives for a chunk with this type and data
+    pub fn compute_crc(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+        Chunk::calc_crc(chunk_type, data, CrcAlgorithm::default())
+    }
+
+    /*
+    True when the reserved bit is set and the stored crc only fits the type with
+    it cleared, which points at the type being changed after the crc was computed
+    */
+    pub fn crc_matches_with_reserved_bit_cleared(&self) -> bool {
+        Chunk::crc_matches_cleared(&self.chunk_type, &self.data, self.crc)
+    }
+
+    /*
+    Parses a chunk, keeping the stored crc as is without validating it
+
+    Used by `Png::try_from_with_policy`
+    */
+    pub fn try_from_unchecked(arr: &[u8]) -> Result<Chunk> {
+        ChunkRef::try_from_unchecked(arr).map(|chunk| chunk.to_chunk())
+    }
+
     /*
     Scans an array of bytes assumed to contain at least one chunk
     Returns a Result containing
@@ -87,12 +325,48 @@ impl Chunk {
             };
         }
 
-        Ok(overhead + len)
+        match overhead.checked_add(len) {
+            Some(total) => Ok(total),
+            None => Err("chunk length too large"),
+        }
     }
 
-    fn calc_crc(chunk_type: &ChunkType, data: &[u8]) -> u32 {
-        let check_me = [&ChunkType::bytes(&chunk_type)[..], &data[..]].concat();
-        crc::crc32::checksum_ieee(&check_me)
+    fn split_at_null(data: &[u8]) -> Result<(&[u8], &[u8])> {
+        match data.iter().position(|b| *b == 0) {
+            Some(i) => Ok((&data[..i], &data[i + 1..])),
+            None => Err("missing null separator"),
+        }
+    }
+
+    // Latin-1 maps each byte directly onto the matching unicode code point
+    fn latin1_to_string(data: &[u8]) -> String {
+        data.iter().map(|b| *b as char).collect()
+    }
+
+    // None when a character is outside of Latin-1
+    fn string_to_latin1(s: &str) -> Option<Vec<u8>> {
+        s.chars().map(|c| u8::try_from(c as u32).ok()).collect()
+    }
+
+    fn serialized_matches(bytes: &[u8], length: u32, crc: u32) -> bool {
+        if bytes.len() < 12 {
+            return false;
+        }
+
+        let (head, tail) = (&bytes[..4], &bytes[bytes.len() - 4..]);
+
+        u32::from_be_bytes([head[0], head[1], head[2], head[3]]) == length
+            && u32::from_be_bytes([tail[0], tail[1], tail[2], tail[3]]) == crc
+    }
+
+    fn crc_matches_cleared(chunk_type: &ChunkType, data: &[u8], crc: u32) -> bool {
+        !chunk_type.is_reserved_bit_valid()
+            && Chunk::calc_crc(&chunk_type.set_reserved_bit_valid(true), data, CrcAlgorithm::default()) == crc
+    }
+
+    fn calc_crc(chunk_type: &ChunkType, data: &[u8], algorithm: CrcAlgorithm) -> u32 {
+        let check_me = [&ChunkType::bytes(chunk_type)[..], data].concat();
+        algorithm.checksum(&check_me)
     }
 }
 
@@ -100,77 +374,136 @@ impl TryFrom<&[u8]> for Chunk {
     type Error = Error;
 
     fn try_from(arr: &[u8]) -> Result<Self> {
-        let mut iter = arr.iter();
+        ChunkRef::try_from(arr).map(|chunk| chunk.to_chunk())
+    }
+}
 
-        // length, 4 bytes
-        let mut len: u32 = 0;
+impl std::fmt::Display for Chunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self.data_as_string() {
+            Ok(s) => s,
+            Err(_) => panic!(),
+        };
 
-        for _i in 0..4 {
-            len *= 256;
-            len += match iter.next() {
-                Some(i) => *i as u32,
-                None => return Err("ran out of bytes reading length"),
-            };
-        }
+        write!(f, "{}", s)
+    }
+}
 
-        // type, 4 bytes
-        let mut type_arr: [u8; 4] = [0; 4];
+/*
+A chunk that borrows its data from the buffer it was parsed from
 
-        for i in 0..4 {
-            type_arr[i] = match iter.next() {
-                Some(i) => *i,
-                None => return Err("ran out of bytes reading chunk type"),
-            };
-        }
+Has the same accessors as `Chunk`, use `to_chunk` for a copy that can be changed
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkRef<'a> {
+    length: u32,
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+}
 
-        let chunk_type = match ChunkType::try_from(type_arr) {
-            Ok(c) => c,
-            Err(_) => return Err("error creating chunk type"),
-        };
+impl<'a> ChunkRef<'a> {
+    pub fn length(&self) -> u32 {
+        self.length
+    }
 
-        // data, length bytes
-        let mut data: Vec<u8> = vec![];
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
 
-        for _i in 0..len {
-            data.push(match iter.next() {
-                Some(i) => *i,
-                None => return Err("ran out of bytes reading chunk data"),
-            });
-        }
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
 
-        // crc, 4 bytes
-        let mut crc: u32 = 0;
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
 
-        for _i in 0..4 {
-            crc *= 256;
-            crc += match iter.next() {
-                Some(i) => *i as u32,
-                None => return Err("ran out of bytes reading crc"),
-            };
-        }
+    pub fn total_len(&self) -> usize {
+        12 + self.data.len()
+    }
 
-        // validate crc
-        if Chunk::calc_crc(&chunk_type, &data) != crc {
-            return Err("invalid crc");
+    pub fn data_as_string(&self) -> Result<String> {
+        Ok(Chunk::latin1_to_string(self.data))
+    }
+
+    pub fn has_valid_crc(&self) -> bool {
+        self.has_valid_crc_with(CrcAlgorithm::default())
+    }
+
+    pub fn has_valid_crc_with(&self, algorithm: CrcAlgorithm) -> bool {
+        Chunk::calc_crc(&self.chunk_type, self.data, algorithm) == self.crc
+    }
+
+    pub fn crc_matches_with_reserved_bit_cleared(&self) -> bool {
+        Chunk::crc_matches_cleared(&self.chunk_type, self.data, self.crc)
+    }
+
+    // Copies the data, keeping the stored crc as is
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk {
+            length: self.length,
+            chunk_type: self.chunk_type,
+            data: self.data.to_vec(),
+            crc: self.crc,
         }
+    }
+
+    // Parses a chunk without validating the stored crc, anything after the chunk in `arr` is ignored
+    pub fn try_from_unchecked(arr: &'a [u8]) -> Result<ChunkRef<'a>> {
+        let read_u32 = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+
+        // length, 4 bytes
+        let len = match arr.get(0..4) {
+            Some(b) => read_u32(b),
+            None => return Err("ran out of bytes reading length"),
+        };
+
+        // type, 4 bytes
+        let chunk_type = match arr.get(4..8) {
+            Some(b) => match ChunkType::try_from([b[0], b[1], b[2], b[3]]) {
+                Ok(c) => c,
+                Err(_) => return Err("error creating chunk type"),
+            },
+            None => return Err("ran out of bytes reading chunk type"),
+        };
+
+        // data, length bytes
+        let data_end = match 8usize.checked_add(len as usize) {
+            Some(end) if end <= arr.len() => end,
+            _ => return Err("ran out of bytes reading chunk data"),
+        };
 
-        Ok(Chunk {
+        // crc, 4 bytes
+        let crc = match arr.get(data_end..data_end + 4) {
+            Some(b) => read_u32(b),
+            None => return Err("ran out of bytes reading crc"),
+        };
+
+        Ok(ChunkRef {
             length: len,
-            chunk_type: chunk_type,
-            data: data,
-            crc: crc,
+            chunk_type,
+            data: &arr[8..data_end],
+            crc,
         })
     }
 }
 
-impl std::fmt::Display for Chunk {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let s = match self.data_as_string() {
-            Ok(s) => s,
-            Err(_) => panic!(),
-        };
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+    type Error = Error;
 
-        write!(f, "{}", s)
+    fn try_from(arr: &'a [u8]) -> Result<Self> {
+        let chunk = ChunkRef::try_from_unchecked(arr)?;
+
+        if chunk.crc_matches_with_reserved_bit_cleared() {
+            return Err(RESERVED_BIT_CRC_HINT);
+        }
+
+        if !chunk.has_valid_crc() {
+            return Err("invalid crc");
+        }
+
+        Ok(chunk)
     }
 }
 
@@ -222,6 +555,38 @@ mod tests {
         assert_eq!(chunk.crc(), 2882656334);
     }
 
+    #[test]
+    fn test_compute_crc_and_crc_matches() {
+        let chunk = testing_chunk();
+
+        assert_eq!(Chunk::compute_crc(chunk.chunk_type(), chunk.data()), chunk.crc());
+        assert_eq!(Chunk::compute_crc(chunk.chunk_type(), chunk.data()), 2882656334);
+        assert!(chunk.crc_matches(2882656334));
+        assert!(!chunk.crc_matches(2882656333));
+    }
+
+    #[test]
+    fn test_verify_serialization() {
+        let chunk = testing_chunk();
+        let bytes = chunk.as_bytes();
+
+        assert!(chunk.verify_serialization());
+        assert_eq!(&bytes[..4], &[0, 0, 0, 42]);
+        assert_eq!(&bytes[bytes.len() - 4..], &2882656334u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_verify_serialization_with_high_length_bytes() {
+        // 0x01000102 sets the highest and the two lowest length bytes, the data is left out
+        let length: u32 = 0x0100_0102;
+        let bytes: Vec<u8> = [&length.to_be_bytes()[..], b"ruSt", &0xdeadbeefu32.to_be_bytes()].concat();
+
+        assert_eq!(Chunk::get_total_length_from_bytes(&bytes), Ok(length + 12));
+        assert!(Chunk::serialized_matches(&bytes, length, 0xdeadbeef));
+        assert!(!Chunk::serialized_matches(&bytes, length.swap_bytes(), 0xdeadbeef));
+        assert!(!Chunk::serialized_matches(&bytes, length, 0xefbeadde));
+    }
+
     #[test]
     fn test_valid_chunk_from_bytes() {
         let data_length: u32 = 42;
@@ -270,6 +635,275 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_content_eq() {
+        let mut bytes = testing_chunk().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        let corrupted = Chunk::try_from_unchecked(&bytes).unwrap();
+
+        assert!(testing_chunk().content_eq(&corrupted));
+        assert_ne!(testing_chunk(), corrupted);
+        assert_eq!(testing_chunk(), testing_chunk());
+    }
+
+    #[test]
+    fn test_set_chunk_type() {
+        use std::str::FromStr;
+
+        let mut chunk = testing_chunk();
+        chunk.set_chunk_type(ChunkType::from_str("ruSt").unwrap());
+
+        assert_eq!(chunk.chunk_type().as_str(), "ruSt");
+        assert!(chunk.has_valid_crc());
+        assert!(Chunk::try_from(chunk.as_bytes().as_ref()).is_ok());
+    }
+
+    #[test]
+    fn test_default_crc_algorithm() {
+        use std::str::FromStr;
+
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let data = b"This is where your secret message will be!".to_vec();
+        let chunk = Chunk::new_with_crc_algorithm(chunk_type, data, CrcAlgorithm::default());
+
+        assert_eq!(CrcAlgorithm::default(), CrcAlgorithm::Ieee);
+        assert_eq!(chunk.crc(), 2882656334);
+        assert_eq!(chunk.crc(), testing_chunk().crc());
+        assert!(chunk.has_valid_crc());
+    }
+
+    #[test]
+    #[cfg(feature = "experimental-crc")]
+    fn test_experimental_crc_algorithm() {
+        use std::str::FromStr;
+
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let data = b"This is where your secret message will be!".to_vec();
+        let chunk = Chunk::new_with_crc_algorithm(chunk_type, data, CrcAlgorithm::Castagnoli);
+
+        assert!(chunk.has_valid_crc_with(CrcAlgorithm::Castagnoli));
+        assert!(!chunk.has_valid_crc());
+    }
+
+    fn text_chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        use std::str::FromStr;
+
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn zlib_compress(data: &[u8]) -> Vec<u8> {
+        use flate2::write::ZlibEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_keyword_and_text_text() {
+        let chunk = text_chunk("tEXt", b"Author\0Caf\xe9".to_vec());
+        let (keyword, text) = chunk.keyword_and_text().unwrap();
+
+        assert_eq!(keyword, "Author");
+        assert_eq!(text, "Caf\u{e9}");
+    }
+
+    #[test]
+    fn test_keyword_and_text_compressed() {
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(zlib_compress(b"squashed text"));
+
+        let chunk = text_chunk("zTXt", data);
+        let (keyword, text) = chunk.keyword_and_text().unwrap();
+
+        assert_eq!(keyword, "Comment");
+        assert_eq!(text, "squashed text");
+    }
+
+    #[test]
+    fn test_keyword_and_text_international() {
+        let mut data = b"Title\0\0\0en\0Title\0".to_vec();
+        data.extend("gr\u{fc}\u{df}e".as_bytes());
+
+        let chunk = text_chunk("iTXt", data);
+        let (keyword, text) = chunk.keyword_and_text().unwrap();
+
+        assert_eq!(keyword, "Title");
+        assert_eq!(text, "gr\u{fc}\u{df}e");
+    }
+
+    #[test]
+    fn test_keyword_and_text_international_compressed() {
+        let mut data = b"Title\0\x01\0de\0Titel\0".to_vec();
+        data.extend(zlib_compress("gr\u{fc}\u{df}e".as_bytes()));
+
+        let chunk = text_chunk("iTXt", data);
+        let (keyword, text) = chunk.keyword_and_text().unwrap();
+
+        assert_eq!(keyword, "Title");
+        assert_eq!(text, "gr\u{fc}\u{df}e");
+    }
+
+    #[test]
+    fn test_keyword_and_text_errors() {
+        assert!(testing_chunk().keyword_and_text().is_err());
+        assert!(text_chunk("tEXt", b"no separator".to_vec()).keyword_and_text().is_err());
+        assert!(text_chunk("zTXt", b"Comment\0\x01junk".to_vec()).keyword_and_text().is_err());
+    }
+
+    #[test]
+    fn test_text() {
+        let chunk = Chunk::text("Comment", "Caf\u{e9} au lait").unwrap();
+
+        assert_eq!(&chunk.chunk_type().to_string(), "tEXt");
+        assert_eq!(chunk.data(), b"Comment\0Caf\xe9 au lait");
+        assert!(chunk.has_valid_crc());
+
+        let (keyword, text) = chunk.keyword_and_text().unwrap();
+        assert_eq!(&keyword, "Comment");
+        assert_eq!(&text, "Caf\u{e9} au lait");
+    }
+
+    #[test]
+    fn test_text_invalid_keyword() {
+        assert_eq!(Chunk::text(&"k".repeat(80), "text").err(), Some("keyword must be 1 to 79 characters"));
+        assert!(Chunk::text(&"k".repeat(79), "text").is_ok());
+        assert_eq!(Chunk::text("", "text").err(), Some("keyword must be 1 to 79 characters"));
+        assert!(Chunk::text(" Comment", "text").is_err());
+        assert!(Chunk::text("Two  spaces", "text").is_err());
+        assert_eq!(Chunk::text("Snow \u{2603}", "text").err(), Some("keyword must be Latin-1"));
+        assert!(Chunk::text("Comment", "null\0byte").is_err());
+    }
+
+    #[test]
+    fn test_total_len() {
+        let chunks = vec![
+            testing_chunk(),
+            text_chunk("tEXt", vec![]),
+            text_chunk("tEXt", vec![0; 1000]),
+        ];
+
+        for chunk in chunks {
+            assert_eq!(chunk.total_len(), chunk.as_bytes().len());
+        }
+    }
+
+    #[test]
+    fn test_set_data() {
+        let mut chunk = testing_chunk();
+        chunk.set_data(b"new data".to_vec());
+
+        let expected = Chunk::new(*chunk.chunk_type(), b"new data".to_vec());
+        assert_eq!(chunk, expected);
+        assert!(chunk.has_valid_crc());
+    }
+
+    #[test]
+    fn test_new_bounded() {
+        let chunk_type: ChunkType = "ruSt".parse().unwrap();
+
+        assert_eq!(Chunk::new_bounded(chunk_type, vec![1; 15], 16), Ok(Chunk::new(chunk_type, vec![1; 15])));
+        assert_eq!(Chunk::new_bounded(chunk_type, vec![1; 16], 16), Ok(Chunk::new(chunk_type, vec![1; 16])));
+        assert_eq!(
+            Chunk::new_bounded(chunk_type, vec![1; 17], 16),
+            Err("data is larger than the maximum message size")
+        );
+        assert!(Chunk::new_bounded(chunk_type, vec![], 0).is_ok());
+    }
+
+    #[test]
+    fn test_from_type_and_str() {
+        let chunk = Chunk::from_type_and_str("RuSt", "This is where your secret message will be!").unwrap();
+        assert_eq!(chunk, testing_chunk());
+
+        let chunk = Chunk::from_type_and_str("ruSt", "caf\u{e9}").unwrap();
+        assert_eq!(chunk, Chunk::new("ruSt".parse().unwrap(), "caf\u{e9}".as_bytes().to_vec()));
+        assert_eq!(chunk.length(), 5);
+
+        assert!(Chunk::from_type_and_str("ru5t", "message").is_err());
+        assert!(Chunk::from_type_and_str("rust!", "message").is_err());
+    }
+
+    #[test]
+    fn test_data_as_cstring() {
+        let chunk_type = *testing_chunk().chunk_type();
+
+        let chunk = Chunk::new(chunk_type, b"Title\0Some text\0more".to_vec());
+        assert_eq!(chunk.data_as_cstring(), Ok("Title".to_string()));
+        assert_eq!(chunk.data_after_cstring(), b"Some text\0more");
+
+        let chunk = Chunk::new(chunk_type, b"No terminator".to_vec());
+        assert_eq!(chunk.data_as_cstring(), Ok("No terminator".to_string()));
+        assert!(chunk.data_after_cstring().is_empty());
+
+        let chunk = Chunk::new(chunk_type, b"\0".to_vec());
+        assert_eq!(chunk.data_as_cstring(), Ok(String::new()));
+        assert!(chunk.data_after_cstring().is_empty());
+
+        let chunk = Chunk::new(chunk_type, vec![0xff, 0xfe, 0, b'a']);
+        assert!(chunk.data_as_cstring().is_err());
+        assert_eq!(chunk.data_after_cstring(), b"a");
+    }
+
+    #[test]
+    fn test_crc_hint_for_altered_reserved_bit() {
+        let mut bytes = testing_chunk().as_bytes();
+        // `RuSt` becomes `Rust`, leaving the crc as it was
+        bytes[6] |= 0x20;
+
+        assert_eq!(Chunk::try_from(bytes.as_slice()), Err(RESERVED_BIT_CRC_HINT));
+        assert!(ChunkRef::try_from_unchecked(&bytes).unwrap().crc_matches_with_reserved_bit_cleared());
+
+        // any other change is just an invalid crc
+        bytes[7] ^= 0x20;
+        assert_eq!(Chunk::try_from(bytes.as_slice()), Err("invalid crc"));
+        assert!(!ChunkRef::try_from_unchecked(&bytes).unwrap().crc_matches_with_reserved_bit_cleared());
+    }
+
+    #[test]
+    fn test_append_data() {
+        let mut chunk = testing_chunk();
+        let expected_data = [chunk.data(), b", second"].concat();
+        chunk.append_data(b", second");
+
+        assert_eq!(chunk.length() as usize, expected_data.len());
+        assert_eq!(chunk, Chunk::new(*chunk.chunk_type(), expected_data));
+        assert!(chunk.has_valid_crc());
+    }
+
+    #[test]
+    fn test_data_mut_then_recompute_crc() {
+        let mut chunk = testing_chunk();
+
+        chunk.data_mut().extend_from_slice(b" and more");
+        assert!(!chunk.has_valid_crc());
+
+        chunk.recompute_crc();
+        assert!(chunk.has_valid_crc());
+        assert_eq!(chunk.length() as usize, chunk.data().len());
+    }
+
+    #[test]
+    fn test_data_mut_round_trip() {
+        let mut chunk = testing_chunk();
+        chunk.data_mut().truncate(4);
+        chunk.data_mut().push(b'!');
+
+        // a stale length makes the serialized chunk unreadable
+        assert!(Chunk::try_from(chunk.as_bytes().as_ref()).is_err());
+
+        chunk.sync_length();
+        assert_eq!(chunk.length(), 5);
+        assert_eq!(Chunk::try_from(chunk.as_bytes().as_ref()), Err("invalid crc"));
+
+        chunk.recompute_crc();
+        assert_eq!(Chunk::try_from(chunk.as_bytes().as_ref()), Ok(chunk));
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -1,37 +1,96 @@
+use std::borrow::Cow;
+
 use crate::{Error, Result};
 
-#[derive(Debug)]
-pub struct ChunkType(u8, u8, u8, u8);
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkType([u8; 4]);
 
 impl ChunkType {
+    // Keeps any bytes, for showing the type of a damaged chunk, check `is_valid` before relying on it
+    pub fn from_bytes_lossy(bytes: [u8; 4]) -> ChunkType {
+        ChunkType(bytes)
+    }
+
     pub fn bytes(&self) -> [u8; 4] {
-        [self.0, self.1, self.2, self.3]
+        self.0
+    }
+
+    // Borrows the type as a string without allocating unless it came from `from_bytes_lossy`
+    pub fn as_str(&self) -> Cow<'_, str> {
+        match std::str::from_utf8(&self.0) {
+            Ok(s) => Cow::Borrowed(s),
+            Err(_) => String::from_utf8_lossy(&self.0),
+        }
+    }
+
+    pub fn eq_str(&self, s: &str) -> bool {
+        self.0 == s.as_bytes()
     }
 
     pub fn is_valid(&self) -> bool {
-        self.is_reserved_bit_valid()
+        self.0.iter().all(|b| ChunkType::byte_is_valid(*b)) && self.is_reserved_bit_valid()
     }
 
     pub fn is_critical(&self) -> bool {
-        self.0 & (ChunkType::FIFTH_BIT) == 0
+        self.0[0] & (ChunkType::FIFTH_BIT) == 0
     }
 
     pub fn is_public(&self) -> bool {
-        self.1 & (ChunkType::FIFTH_BIT) == 0
+        self.0[1] & (ChunkType::FIFTH_BIT) == 0
     }
 
     pub fn is_reserved_bit_valid(&self) -> bool {
-        self.2 & (ChunkType::FIFTH_BIT) == 0
+        self.0[2] & (ChunkType::FIFTH_BIT) == 0
     }
 
     pub fn is_safe_to_copy(&self) -> bool {
-        self.3 & (ChunkType::FIFTH_BIT) != 0
+        self.0[3] & (ChunkType::FIFTH_BIT) != 0
+    }
+
+    // The four property bits in byte order, e.g. "ancillary, private, reserved-valid, safe-to-copy"
+    pub fn property_string(&self) -> String {
+        [
+            if self.is_critical() { "critical" } else { "ancillary" },
+            if self.is_public() { "public" } else { "private" },
+            if self.is_reserved_bit_valid() { "reserved-valid" } else { "reserved-invalid" },
+            if self.is_safe_to_copy() { "safe-to-copy" } else { "unsafe-to-copy" },
+        ]
+        .join(", ")
+    }
+
+    pub fn set_critical(&self, critical: bool) -> ChunkType {
+        self.with_fifth_bit(0, !critical)
+    }
+
+    pub fn set_public(&self, public: bool) -> ChunkType {
+        self.with_fifth_bit(1, !public)
+    }
+
+    pub fn set_reserved_bit_valid(&self, valid: bool) -> ChunkType {
+        self.with_fifth_bit(2, !valid)
+    }
+
+    pub fn set_safe_to_copy(&self, safe_to_copy: bool) -> ChunkType {
+        self.with_fifth_bit(3, safe_to_copy)
     }
 
     const FIFTH_BIT: u8 = 0b0010_0000;
 
+    // Flipping the fifth bit switches a letter's case, so the result is still valid
+    fn with_fifth_bit(&self, i: usize, set: bool) -> ChunkType {
+        let mut bytes = self.0;
+
+        if set {
+            bytes[i] |= ChunkType::FIFTH_BIT;
+        } else {
+            bytes[i] &= !ChunkType::FIFTH_BIT;
+        }
+
+        ChunkType(bytes)
+    }
+
     fn byte_is_valid(b: u8) -> bool {
-        (b >= 'a' as u8 && b <= 'z' as u8) || (b >= 'A' as u8 && b <= 'Z' as u8)
+        b.is_ascii_lowercase() || b.is_ascii_uppercase()
     }
 
     fn from_arr(arr: [u8; 4]) -> Result<Self> {
@@ -43,7 +102,7 @@ impl ChunkType {
             return Err("Invalid chunk byte value");
         }
 
-        Ok(ChunkType(arr[0], arr[1], arr[2], arr[3]))
+        Ok(ChunkType(arr))
     }
 }
 
@@ -74,17 +133,13 @@ impl std::str::FromStr for ChunkType {
 
 impl std::cmp::PartialEq for ChunkType {
     fn eq(&self, other: &ChunkType) -> bool {
-        self.0 == other.0 && self.1 == other.1 && self.2 == other.2 && self.3 == other.3
+        self.0 == other.0
     }
 }
 
 impl std::fmt::Display for ChunkType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}{}{}{}",
-            self.0 as char, self.1 as char, self.2 as char, self.3 as char
-        )
+        write!(f, "{}", self.as_str())
     }
 }
 
@@ -172,12 +227,92 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_chunk_type_property_string() {
+        assert_eq!(
+            ChunkType::from_str("ruSt").unwrap().property_string(),
+            "ancillary, private, reserved-valid, safe-to-copy"
+        );
+        assert_eq!(
+            ChunkType::from_str("IHDR").unwrap().property_string(),
+            "critical, public, reserved-valid, unsafe-to-copy"
+        );
+        assert_eq!(
+            ChunkType::from_str("Rust").unwrap().property_string(),
+            "critical, private, reserved-invalid, safe-to-copy"
+        );
+    }
+
+    #[test]
+    pub fn test_lossy_chunk_type_is_valid() {
+        assert!(ChunkType::from_bytes_lossy(*b"RuSt").is_valid());
+        assert!(!ChunkType::from_bytes_lossy(*b"Rust").is_valid());
+        assert!(!ChunkType::from_bytes_lossy(*b"Ru1t").is_valid());
+        // `@` has the fifth bit clear like an uppercase letter, but isn't one
+        assert!(!ChunkType::from_bytes_lossy(*b"Ru@t").is_valid());
+        assert!(!ChunkType::from_bytes_lossy([0xff, 0, b'S', b't']).is_valid());
+        assert_eq!(ChunkType::from_bytes_lossy(*b"Ru1t").to_string(), "Ru1t");
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
         assert_eq!(&chunk.to_string(), "RuSt");
     }
 
+    #[test]
+    pub fn test_chunk_type_as_str() {
+        let chunk = ChunkType::from_str("RuSt").unwrap();
+        assert_eq!(chunk.as_str(), "RuSt");
+        assert!(matches!(chunk.as_str(), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    pub fn test_chunk_type_set_critical() {
+        let chunk = ChunkType::from_str("ruSt").unwrap();
+
+        assert_eq!(chunk.set_critical(true).as_str(), "RuSt");
+        assert_eq!(chunk.set_critical(false).as_str(), "ruSt");
+        assert!(chunk.set_critical(true).is_critical());
+    }
+
+    #[test]
+    pub fn test_chunk_type_set_public() {
+        let chunk = ChunkType::from_str("ruSt").unwrap();
+
+        assert_eq!(chunk.set_public(true).as_str(), "rUSt");
+        assert_eq!(chunk.set_public(false).as_str(), "ruSt");
+        assert!(chunk.set_public(true).is_public());
+    }
+
+    #[test]
+    pub fn test_chunk_type_set_reserved_bit_valid() {
+        let chunk = ChunkType::from_str("Rust").unwrap();
+
+        assert_eq!(chunk.set_reserved_bit_valid(true).as_str(), "RuSt");
+        assert_eq!(chunk.set_reserved_bit_valid(false).as_str(), "Rust");
+        assert!(chunk.set_reserved_bit_valid(true).is_valid());
+    }
+
+    #[test]
+    pub fn test_chunk_type_set_safe_to_copy() {
+        let chunk = ChunkType::from_str("RuST").unwrap();
+
+        assert_eq!(chunk.set_safe_to_copy(true).as_str(), "RuSt");
+        assert_eq!(chunk.set_safe_to_copy(false).as_str(), "RuST");
+        assert!(chunk.set_safe_to_copy(true).is_safe_to_copy());
+        assert!(chunk.set_safe_to_copy(true).is_valid());
+    }
+
+    #[test]
+    pub fn test_chunk_type_eq_str() {
+        let chunk = ChunkType::from_str("RuSt").unwrap();
+
+        assert!(chunk.eq_str("RuSt"));
+        assert!(!chunk.eq_str("rust"));
+        assert!(!chunk.eq_str("RuSt "));
+    }
+
     #[test]
     pub fn test_chunk_type_trait_impls() {
         let chunk_type_1: ChunkType = TryFrom::try_from([82, 117, 83, 116]).unwrap();
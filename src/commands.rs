@@ -1,120 +1,3737 @@
 use std::io::prelude::*;
-use std::fs::File;
+use std::fs::{self, File};
 use std::process;
 use std::convert::TryFrom;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::SystemTime;
+#[cfg(any(feature = "network", feature = "watch"))]
+use std::time::Duration;
 
-use crate::png::Png;
-use crate::chunk::Chunk;
+use crate::png::{CrcPolicy, ParseLimits, Png};
+use crate::chunk::{Chunk, RESERVED_BIT_CRC_HINT};
 use crate::chunk_type::ChunkType;
+use crate::info;
 
-pub fn encode(filename: &str, chunk_type: &str, msg: &str, output_filename: &str) {
-    let mut png = read_png_from_file(filename);
+// How input files are read and parsed, shared by every command
+pub struct ReadOptions {
+    pub crc_policy: CrcPolicy,
+    // input is base64, detected automatically for data URIs
+    pub base64: bool,
+    // input may be a bare chunk stream without the PNG signature
+    pub assume_raw: bool,
+    // bounds on reading from a URL, so an untrusted server can't stream forever
+    #[cfg(feature = "network")]
+    pub max_download: usize,
+    #[cfg(feature = "network")]
+    pub download_timeout: Duration,
+}
 
-    let chunk_type = match ChunkType::from_str(chunk_type) {
-        Ok(c) => c,
-        Err(err) => {
-            eprintln!("Invalid chunk type: {}", err);
-            process::exit(1);
-        },
-    };
-    
-    let chunk = Chunk::new(chunk_type, msg.as_bytes().to_vec());
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions {
+            crc_policy: CrcPolicy::All,
+            base64: false,
+            assume_raw: false,
+            #[cfg(feature = "network")]
+            max_download: DEFAULT_MAX_DOWNLOAD,
+            #[cfg(feature = "network")]
+            download_timeout: DEFAULT_DOWNLOAD_TIMEOUT,
+        }
+    }
+}
 
-    png.append_chunk(chunk);
+#[cfg(feature = "network")]
+pub const DEFAULT_MAX_DOWNLOAD: usize = 50 * 1024 * 1024;
 
-    write_file(output_filename, png.as_bytes().as_slice());
+// Applies to connecting and to each read
+#[cfg(feature = "network")]
+pub const DEFAULT_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How `list` and `count` print their rows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Csv,
 }
 
-pub fn decode(filename: &str, chunk_type: &str) {
-    let png = read_png_from_file(filename);
+impl FromStr for OutputFormat {
+    type Err = crate::Error;
 
-    let chunk = match png.chunk_by_type(chunk_type) {
-        Some(c) => c,
-        _ => {
-            eprintln!("Chunk type `{}` not found", chunk_type);
-            process::exit(1);
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err("Invalid output format"),
+        }
+    }
+}
+
+// Digests the `checksum` command can print
+#[cfg(feature = "checksum")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+}
+
+#[cfg(feature = "checksum")]
+impl ChecksumAlgorithm {
+    // Lowercase hex, as printed by `sha256sum`
+    fn hex_digest(&self, bytes: &[u8]) -> String {
+        use sha2::Digest;
+
+        let digest = match self {
+            ChecksumAlgorithm::Sha256 => sha2::Sha256::digest(bytes),
+        };
+
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+#[cfg(feature = "checksum")]
+impl FromStr for ChecksumAlgorithm {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s {
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            _ => Err("Invalid checksum algorithm"),
+        }
+    }
+}
+
+// Chunks above this size are rejected by some viewers
+pub const DEFAULT_MAX_CHUNK_SIZE: usize = 10 * 1024 * 1024;
+
+pub struct EncodeOptions {
+    // only warns, the hard limit is u32::MAX
+    pub max_chunk_size: usize,
+    // fail instead of replacing an existing output file, including FILE itself
+    pub no_overwrite: bool,
+    // store the message with `frame_message`
+    pub framed: bool,
+    // see `disallowed_text_byte`
+    pub strict_ascii: bool,
+    // replace every existing chunk of the type instead of adding another one, see `Png::replace_all`
+    pub upsert: bool,
+    // append the message to the first existing chunk of the type, see `concat_or_add_chunk`
+    pub concat: bool,
+    // right-pad the chunk data with zero bytes to exactly this length
+    pub pad_to: Option<usize>,
+    // set the safe-to-copy bit of the given chunk type so editors keep the chunk
+    pub safe_to_copy: bool,
+    // a hard limit on the new chunk's data, unlike `max_chunk_size`
+    pub max_message_size: Option<usize>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            no_overwrite: false,
+            framed: false,
+            strict_ascii: false,
+            upsert: false,
+            concat: false,
+            pad_to: None,
+            safe_to_copy: false,
+            max_message_size: None,
+        }
+    }
+}
+
+// Set once from the command line, see `checked_png_bytes`
+static VERIFY_WRITES: AtomicBool = AtomicBool::new(true);
+
+pub fn set_verify_writes(enabled: bool) {
+    VERIFY_WRITES.store(enabled, Ordering::Relaxed);
+}
+
+// Set once from the command line, decides how `exit_with` prints failures
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_json_errors(enabled: bool) {
+    JSON_ERRORS.store(enabled, Ordering::Relaxed);
+}
+
+// Set once from the command line, see `overwritten_mtime`
+static PRESERVE_MTIME: AtomicBool = AtomicBool::new(false);
+
+pub fn set_preserve_mtime(enabled: bool) {
+    PRESERVE_MTIME.store(enabled, Ordering::Relaxed);
+}
+
+// With no chunk type, an unused private type is picked and reported
+pub fn encode(filename: &str, chunk_type: Option<&str>, msg: &str, output_filename: &str, options: &EncodeOptions, read_options: &ReadOptions) {
+    let mut png = read_png_from_file(filename, read_options);
+
+    let data = if options.framed {
+        frame_message(msg.as_bytes())
+    } else {
+        msg.as_bytes().to_vec()
+    };
+
+    let data = match options.pad_to {
+        Some(len) => match pad_data(data, len) {
+            Ok(data) => data,
+            Err(err) => fail("invalid_input", format!("Error padding message: {}", err)),
         },
+        None => data,
     };
 
-    let chunk_string = match chunk.data_as_string() {
-        Ok(s) => s,
-        Err(err) => {
-            eprintln!("Error reading chunk data: {}", err);
-            process::exit(1);
+    if data.len() > u32::MAX as usize {
+        fail("invalid_input", "Message is too large to fit in a chunk".to_string());
+    }
+
+    let chunk_type = match chunk_type {
+        Some(chunk_type) => match ChunkType::from_str(chunk_type) {
+            Ok(c) => c,
+            Err(err) => fail("invalid_chunk_type", format!("Invalid chunk type: {}", err)),
+        },
+        None => match unused_chunk_type(&png) {
+            Some(c) => {
+                println!("Encoded message in chunk type `{}`", c);
+                c
+            }
+            None => fail("invalid_chunk_type", "No unused chunk type left to pick".to_string()),
+        },
+    };
+
+    let chunk_type = if options.safe_to_copy && !chunk_type.is_safe_to_copy() {
+        let adjusted = chunk_type.set_safe_to_copy(true);
+        println!("Changed chunk type `{}` to `{}` so it's safe to copy", chunk_type, adjusted);
+        adjusted
+    } else {
+        chunk_type
+    };
+
+    if options.strict_ascii && TEXT_CHUNK_TYPES.iter().any(|t| chunk_type.eq_str(t)) {
+        if let Some((position, byte)) = disallowed_text_byte(msg.as_bytes()) {
+            fail(
+                "invalid_input",
+                format!("Byte {:#04x} at position {} is not allowed in a `{}` chunk", byte, position, chunk_type),
+            );
         }
+    }
+
+    let chunk = match options.max_message_size {
+        Some(max) => match Chunk::new_bounded(chunk_type, data, max) {
+            Ok(chunk) => chunk,
+            Err(err) => fail("invalid_input", format!("Error encoding chunk: {} of {} bytes", err, max)),
+        },
+        None => Chunk::new(chunk_type, data),
+    };
+
+    let was_valid = png.validate_structure().is_ok();
+    let message_len = chunk.data().len();
+
+    let added = if options.upsert {
+        png.replace_all(&chunk_type.as_str(), vec![chunk]);
+        Ok(())
+    } else if options.concat {
+        concat_or_add_chunk(&mut png, chunk)
+    } else {
+        add_chunk_before_iend(&mut png, chunk).map(|_| ())
     };
 
-    println!("Chunk data: `{}`", chunk_string);
+    if let Err(err) = added {
+        fail("invalid_chunk_type", format!("Error encoding chunk: {}", err));
+    }
+
+    let chunk_len = encoded_chunk_len(&png, &chunk_type, options.concat, message_len);
+
+    if let Some(warning) = chunk_size_warning(chunk_len, options.max_chunk_size) {
+        eprintln!("{}", warning);
+    }
+
+    let bytes = checked_png_bytes(&png, was_valid, read_options.crc_policy);
+
+    if options.no_overwrite {
+        if let Err(report) = write_new_file(output_filename, &bytes) {
+            exit_with(report);
+        }
+    } else if is_same_file(filename, output_filename) {
+        write_file_atomic(output_filename, &bytes);
+    } else {
+        write_file(output_filename, &bytes);
+    }
 }
 
-pub fn remove(filename: &str, chunk_type: &str) {
-    let mut png = read_png_from_file(filename);
+// With `concat` the message was appended to the first chunk of the type, which may now be much larger
+fn encoded_chunk_len(png: &Png, chunk_type: &ChunkType, concat: bool, message_len: usize) -> usize {
+    match png.chunk_by_type(&chunk_type.as_str()) {
+        Some(chunk) if concat => chunk.data().len(),
+        _ => message_len,
+    }
+}
 
-    match png.remove_chunk(chunk_type) {
-        Ok(_) => {},
-        Err(err) => {
-            eprintln!("Error removing chunk: {}", err);
-            process::exit(1);
-        },
+fn pad_data(mut data: Vec<u8>, len: usize) -> crate::Result<Vec<u8>> {
+    if data.len() > len {
+        return Err("message is longer than the padded length");
     }
 
-    write_file(filename, png.as_bytes().as_slice());
+    data.resize(len, 0);
+    Ok(data)
 }
 
-pub fn print(filename: &str) {
-    let png = read_png_from_file(filename);
+// Keeps `IEND` last when there is one, a duplicate `IHDR` or `IEND` is an error
+fn add_chunk_before_iend(png: &mut Png, chunk: Chunk) -> crate::Result<usize> {
+    let is_header_or_end = chunk.chunk_type().eq_str("IHDR") || chunk.chunk_type().eq_str("IEND");
 
-    println!("{}", png);
+    if is_header_or_end || !png.contains_chunk_type("IEND") {
+        png.append_chunk_checked(chunk)
+    } else {
+        png.insert_before("IEND", chunk)
+    }
 }
 
-fn read_png_from_file(filename: &str) -> Png {
-    let contents = read_file(filename);
+// Adds `chunk` as usual when there's no chunk of its type yet
+fn concat_or_add_chunk(png: &mut Png, chunk: Chunk) -> crate::Result<()> {
+    match png.chunk_by_type_mut(&chunk.chunk_type().as_str()) {
+        Some(existing) => {
+            if existing.data().len() + chunk.data().len() > u32::MAX as usize {
+                return Err("concatenated data is too large to fit in a chunk");
+            }
 
-    match Png::try_from(&contents[..]) {
-        Ok(png) => png,
-        Err(err) => {
-            eprintln!("Error parsing PNG {:?}", err);
-            process::exit(1);
+            existing.append_data(chunk.data());
+            Ok(())
         },
+        None => add_chunk_before_iend(png, chunk).map(|_| ()),
     }
 }
 
-fn read_file(filename: &str) -> Vec<u8> {
-    let mut f = match File::open(filename) {
-        Ok(f) => f,
-        Err(err) => {
-            eprintln!("Error opening file `{}`: {:?}", filename, err);
-            process::exit(1);
+/*
+Serializes `png` for writing back, refusing if it was structurally valid when
+read but no longer is, so a command never breaks a working file
+
+Files that were already malformed, or any file with `remove --index --force`, are
+written as they are with a warning. Either way the bytes must parse back with
+`crc_policy`, unless turned off with --no-verify
+*/
+fn checked_png_bytes(png: &Png, was_valid: bool, crc_policy: CrcPolicy) -> Vec<u8> {
+    let bytes = if was_valid {
+        let mut bytes = Vec::with_capacity(png.byte_size());
+
+        if let Err(err) = png.to_writer_checked(&mut bytes) {
+            fail("invalid_structure", format!("Refusing to write a malformed PNG: {}", err));
+        }
+
+        bytes
+    } else {
+        if let Err(err) = png.validate_structure() {
+            eprintln!("Warning: writing a malformed PNG: {}", err);
         }
+
+        png.as_bytes()
     };
 
-    let mut buffer = Vec::new();
-    match f.read_to_end(&mut buffer) {
-        Ok(_) => {},
-        Err(err) => {
-            eprintln!("Error reading file `{}`: {:?}", filename, err);
-            process::exit(1);
+    if VERIFY_WRITES.load(Ordering::Relaxed) {
+        if let Err(err) = verify_round_trip(png, &bytes, crc_policy) {
+            fail("verify", format!("Refusing to write a PNG that doesn't parse back: {}", err));
+        }
+    }
+
+    bytes
+}
+
+// Catches chunks whose length or crc went stale while they were edited, or got written in the wrong byte order
+fn verify_round_trip(png: &Png, bytes: &[u8], crc_policy: CrcPolicy) -> crate::Result<()> {
+    if !png.chunks().iter().all(Chunk::verify_serialization) {
+        return Err("a chunk's length or crc isn't written back as big-endian");
+    }
+
+    let reparsed = Png::try_from_with_policy(bytes, crc_policy)?;
+
+    if reparsed.chunks() != png.chunks() || reparsed.trailing_bytes() != png.trailing_bytes() {
+        return Err("the chunks read back differ from the ones written");
+    }
+
+    Ok(())
+}
+
+/*
+Output path next to `filename`, or in `dir`, with `suffix` inserted before the extension
+
+e.g. `images/cat.png` with suffix `.stego` becomes `images/cat.stego.png`
+*/
+pub fn derive_output_path(filename: &str, suffix: Option<&str>, dir: Option<&str>) -> String {
+    let path = std::path::Path::new(filename);
+
+    let file_name = match suffix {
+        Some(suffix) => {
+            let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+            let extension = path.extension().map(|ext| ext.to_string_lossy()).unwrap_or_else(|| "png".into());
+
+            format!("{}{}.{}", stem, suffix, extension)
         }
+        None => path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+    };
+
+    let parent = match dir {
+        Some(dir) => std::path::Path::new(dir),
+        None => path.parent().unwrap_or_else(|| std::path::Path::new("")),
     };
 
-    buffer
+    parent.join(file_name).to_string_lossy().into_owned()
 }
 
-fn write_file(filename: &str, data: &[u8]) {
-    let mut f = match File::create(filename) {
-        Ok(f) => f,
-        Err(err) => {
-            eprintln!("Error creatubg file `{}`: {:?}", filename, err);
-            process::exit(1);
+// Keeps secrets out of the process list, unlike passing them as an argument
+pub fn message_from_env(name: &str) -> String {
+    match std::env::var(name) {
+        Ok(msg) => msg,
+        Err(std::env::VarError::NotPresent) => fail("invalid_input", format!("Environment variable `{}` is not set", name)),
+        Err(std::env::VarError::NotUnicode(_)) => fail("invalid_input", format!("Environment variable `{}` is not valid unicode", name)),
+    }
+}
+
+/*
+With `json`, prints the chunk with its raw data instead of just the message
+
+`trim_nulls` drops the zero bytes added by `encode --pad-to`
+*/
+pub fn decode(filename: &str, chunk_type: &str, framed: bool, json: bool, trim_nulls: bool, read_options: &ReadOptions) {
+    let png = read_png_from_file(filename, read_options);
+
+    if json {
+        match png.chunk_by_type(chunk_type) {
+            Some(chunk) => println!("{}", chunk_json(chunk)),
+            None => fail("not_found", chunk_not_found_message(&png, chunk_type)),
+        }
+        return;
+    }
+
+    match decode_message(&png, chunk_type, framed) {
+        Ok(msg) if trim_nulls => println!("Chunk data: `{}`", msg.trim_end_matches('\0')),
+        Ok(msg) => println!("Chunk data: `{}`", msg),
+        Err(report) => exit_with(report),
+    }
+}
+
+pub const DEFAULT_MAX_DEPTH: usize = 4;
+
+/*
+Decodes as usual unless the chunk data is itself a PNG, which is then listed,
+along with any PNGs found in its chunks down to `max_depth` levels
+*/
+pub fn decode_recursive(filename: &str, chunk_type: &str, max_depth: usize, read_options: &ReadOptions) {
+    let png = read_png_from_file(filename, read_options);
+
+    let chunk = match png.chunk_by_type(chunk_type) {
+        Some(c) => c,
+        None => fail("not_found", chunk_not_found_message(&png, chunk_type)),
+    };
+
+    if !chunk.data().starts_with(&Png::STANDARD_HEADER) {
+        println!("Chunk data: `{}`", chunk);
+        return;
+    }
+
+    println!("Chunk `{}` holds a PNG:", chunk_type);
+
+    for line in render_embedded_png(chunk.data(), 1, max_depth) {
+        println!("{}", line);
+    }
+}
+
+// One line per chunk of the PNG in `data`, nested PNGs are indented one more level
+fn render_embedded_png(data: &[u8], level: usize, max_depth: usize) -> Vec<String> {
+    let indent = "  ".repeat(level);
+
+    if level > max_depth {
+        return vec![format!("{}(not shown, deeper than --max-depth {})", indent, max_depth)];
+    }
+
+    let png = match Png::try_from(data) {
+        Ok(png) => png,
+        Err(err) => return vec![format!("{}(can't be read: {})", indent, err)],
+    };
+
+    let mut lines = vec![];
+
+    for (i, chunk) in png.chunks().iter().enumerate() {
+        let line = format!("{}{} {} length:{}", indent, i, chunk.chunk_type(), chunk.length());
+
+        if chunk.data().starts_with(&Png::STANDARD_HEADER) {
+            lines.push(format!("{}, holds a PNG:", line));
+            lines.extend(render_embedded_png(chunk.data(), level + 1, max_depth));
+        } else {
+            lines.push(line);
         }
+    }
+
+    lines
+}
+
+/*
+Parses the bytes at `offset` as a chunk on their own, for recovering data from
+files that are too damaged to parse as a PNG
+
+With `chunk_type`, fails if the chunk found there has a different type
+*/
+pub fn decode_at_offset(filename: &str, offset: usize, chunk_type: Option<&str>) {
+    let contents = read_file(filename);
+
+    let chunk = match chunk_at_offset(&contents, offset) {
+        Ok(chunk) => chunk,
+        Err(err) => fail("parse", format!("Error reading chunk at offset {}: {}", offset, err)),
     };
 
-    match f.write_all(data) {
+    if let Some(chunk_type) = chunk_type {
+        if !chunk.chunk_type().eq_str(chunk_type) {
+            fail("not_found", format!("Chunk at offset {} has type `{}`, not `{}`", offset, chunk.chunk_type(), chunk_type));
+        }
+    }
+
+    println!("Chunk {{ type:{}, length:{} }}", chunk.chunk_type(), chunk.length());
+    println!("Chunk data: `{}`", chunk);
+}
+
+fn chunk_at_offset(contents: &[u8], offset: usize) -> crate::Result<Chunk> {
+    match contents.get(offset..) {
+        Some(bytes) => Chunk::try_from(bytes),
+        None => Err("offset is past the end of the file"),
+    }
+}
+
+// Waits this long after a change for others from the same save
+#[cfg(feature = "watch")]
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+
+// Decodes again after every change to the file until interrupted, failures are printed but not fatal
+#[cfg(feature = "watch")]
+pub fn decode_watch(filename: &str, chunk_type: &str, framed: bool, read_options: &ReadOptions) {
+    let decode_once = || match load_png(filename, read_options).and_then(|png| decode_message(&png, chunk_type, framed)) {
+        Ok(msg) => println!("Chunk data: `{}`", msg),
+        Err(report) => print_error(&report),
+    };
+
+    decode_once();
+
+    let result = watch_file(filename, WATCH_DEBOUNCE, || {
+        decode_once();
+        true
+    });
+
+    if let Err(err) = result {
+        fail("io", format!("Error watching file `{}`: {}", filename, err));
+    }
+}
+
+fn decode_message(png: &Png, chunk_type: &str, framed: bool) -> std::result::Result<String, ErrorReport> {
+    let chunk = match png.chunk_by_type(chunk_type) {
+        Some(c) => c,
+        _ => return Err(ErrorReport::new("not_found", chunk_not_found_message(png, chunk_type))),
+    };
+
+    if framed {
+        return unframe_message(chunk.data())
+            .map(|msg| msg.iter().map(|&c| c as char).collect())
+            .map_err(|err| ErrorReport::new("invalid_data", format!("Error reading framed message: {}", err)));
+    }
+
+    chunk
+        .data_as_string()
+        .map_err(|err| ErrorReport::new("invalid_data", format!("Error reading chunk data: {}", err)))
+}
+
+// `data_utf8` is null when the data isn't UTF-8, `data_base64` always has the exact bytes
+fn chunk_json(chunk: &Chunk) -> String {
+    serde_json::json!({
+        "chunk_type": chunk.chunk_type().to_string(),
+        "length": chunk.length(),
+        "crc": format!("{:#010x}", chunk.crc()),
+        "data_utf8": std::str::from_utf8(chunk.data()).ok(),
+        "data_base64": data_uri(chunk.data(), false),
+    })
+    .to_string()
+}
+
+const TEXT_CHUNK_TYPES: [&str; 3] = ["tEXt", "zTXt", "iTXt"];
+
+// Prints the text of the first `tEXt`, `zTXt` or `iTXt` chunk with this keyword
+pub fn decode_keyword(filename: &str, keyword: &str, read_options: &ReadOptions) {
+    let png = read_png_from_file(filename, read_options);
+
+    match text_by_keyword(&png, keyword) {
+        Ok(text) => println!("Chunk data: `{}`", text),
+        Err(report) => exit_with(report),
+    }
+}
+
+// Text chunks that can't be read are skipped
+fn text_by_keyword(png: &Png, keyword: &str) -> std::result::Result<String, ErrorReport> {
+    let texts: Vec<(String, String)> = png
+        .chunks()
+        .iter()
+        .filter(|chunk| TEXT_CHUNK_TYPES.iter().any(|t| chunk.chunk_type().eq_str(t)))
+        .filter_map(|chunk| chunk.keyword_and_text().ok())
+        .collect();
+
+    if let Some((_, text)) = texts.iter().find(|(k, _)| k == keyword) {
+        return Ok(text.clone());
+    }
+
+    let message = if texts.is_empty() {
+        format!("No text chunk with keyword `{}`, the file has no text chunks", keyword)
+    } else {
+        let keywords: Vec<&str> = texts.iter().map(|(k, _)| k.as_str()).collect();
+        format!("No text chunk with keyword `{}`, keywords in the file: {}", keyword, keywords.join(", "))
+    };
+
+    Err(ErrorReport::new("not_found", message))
+}
+
+// The first byte that isn't printable ASCII or a newline, with its position
+fn disallowed_text_byte(msg: &[u8]) -> Option<(usize, u8)> {
+    msg.iter()
+        .copied()
+        .enumerate()
+        .find(|&(_, b)| !(b == b'\n' || (0x20..=0x7e).contains(&b)))
+}
+
+// Marks chunk data written by `encode --framed`
+const FRAME_MAGIC: [u8; 4] = *b"PME1";
+
+// `FRAME_MAGIC`, then the message length as a big endian u32, then the message
+fn frame_message(msg: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(8 + msg.len());
+    data.extend_from_slice(&FRAME_MAGIC);
+    data.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+    data.extend_from_slice(msg);
+    data
+}
+
+// Exactly the framed message, anything after it is ignored
+fn unframe_message(data: &[u8]) -> crate::Result<&[u8]> {
+    if data.len() < 8 || data[0..4] != FRAME_MAGIC {
+        return Err("missing PME1 header, the chunk was not written with --framed");
+    }
+
+    let len = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+
+    match data[8..].get(..len) {
+        Some(msg) => Ok(msg),
+        None => Err("framed length is longer than the chunk data"),
+    }
+}
+
+pub fn decode_all_types(filename: &str, count_only: bool, read_options: &ReadOptions) {
+    let png = read_png_from_file(filename, read_options);
+    let lines = render_ancillary_chunks(&png);
+
+    if count_only {
+        print_count_only(lines.len());
+    }
+
+    for line in lines {
+        println!("{}", line);
+    }
+}
+
+pub fn remove(filename: &str, chunk_type: &str, trailing_to_file: Option<&str>, read_options: &ReadOptions) {
+    let mut png = read_png_from_file(filename, read_options);
+
+    if !png.contains_chunk_type(chunk_type) {
+        fail("not_found", chunk_not_found_message(&png, chunk_type));
+    }
+
+    let was_valid = png.validate_structure().is_ok();
+
+    match png.remove_chunk(chunk_type) {
         Ok(_) => {},
         Err(err) => {
-            eprintln!("Error writing to file `{}`: {:?}", filename, err);
-            process::exit(1);
+            fail("remove", format!("Error removing chunk: {}", err));
+        },
+    }
+
+    if let Some(path) = trailing_to_file {
+        save_trailing_bytes(&mut png, path);
+    }
+
+    write_file(filename, &checked_png_bytes(&png, was_valid, read_options.crc_policy));
+}
+
+pub fn remove_all(filename: &str, chunk_type: &str, quiet: bool, trailing_to_file: Option<&str>, read_options: &ReadOptions) {
+    let mut png = read_png_from_file(filename, read_options);
+
+    if !png.contains_chunk_type(chunk_type) {
+        fail("not_found", chunk_not_found_message(&png, chunk_type));
+    }
+
+    let was_valid = png.validate_structure().is_ok();
+    let removed = png.remove_all_chunks(chunk_type);
+
+    if let Some(path) = trailing_to_file {
+        save_trailing_bytes(&mut png, path);
+    }
+
+    write_file(filename, &checked_png_bytes(&png, was_valid, read_options.crc_policy));
+
+    if !quiet {
+        for line in render_removed_chunks(&removed) {
+            println!("{}", line);
+        }
+    }
+}
+
+// Strips private and other unregistered chunks, keeping the standard metadata
+pub fn remove_unknown(filename: &str, quiet: bool, trailing_to_file: Option<&str>, read_options: &ReadOptions) {
+    let mut png = read_png_from_file(filename, read_options);
+
+    let was_valid = png.validate_structure().is_ok();
+    let removed = png.remove_unknown_chunks();
+
+    if let Some(path) = trailing_to_file {
+        save_trailing_bytes(&mut png, path);
+    }
+
+    if !removed.is_empty() || trailing_to_file.is_some() {
+        write_file(filename, &checked_png_bytes(&png, was_valid, read_options.crc_policy));
+    }
+
+    if !quiet {
+        for line in render_removed_chunks(&removed) {
+            println!("{}", line);
+        }
+    }
+}
+
+// Keeps the first `keep` chunks of the type, then removes at most `max_remove` of the rest
+pub fn remove_bounded(
+    filename: &str,
+    chunk_type: &str,
+    keep: usize,
+    max_remove: Option<usize>,
+    quiet: bool,
+    trailing_to_file: Option<&str>,
+    read_options: &ReadOptions,
+) {
+    let mut png = read_png_from_file(filename, read_options);
+
+    if !png.contains_chunk_type(chunk_type) {
+        fail("not_found", chunk_not_found_message(&png, chunk_type));
+    }
+
+    let was_valid = png.validate_structure().is_ok();
+    let removed = remove_chunks_bounded(&mut png, chunk_type, keep, max_remove);
+
+    if let Some(path) = trailing_to_file {
+        save_trailing_bytes(&mut png, path);
+    }
+
+    write_file(filename, &checked_png_bytes(&png, was_valid, read_options.crc_policy));
+
+    if !quiet {
+        for line in render_removed_chunks(&removed) {
+            println!("{}", line);
         }
     }
 }
+
+fn remove_chunks_bounded(png: &mut Png, chunk_type: &str, keep: usize, max_remove: Option<usize>) -> Vec<(usize, Chunk)> {
+    let mut seen = 0;
+    let mut removed = 0;
+
+    png.remove_chunks_where(|chunk| {
+        if !chunk.chunk_type().eq_str(chunk_type) {
+            return false;
+        }
+
+        seen += 1;
+
+        if seen <= keep || max_remove.is_some_and(|max| removed >= max) {
+            return false;
+        }
+
+        removed += 1;
+        true
+    })
+}
+
+// With `chunk_type`, the chunk has to be of that type as well
+pub fn remove_by_crc(filename: &str, crc: u32, chunk_type: Option<&str>, trailing_to_file: Option<&str>, read_options: &ReadOptions) {
+    let mut png = read_png_from_file(filename, read_options);
+    let was_valid = png.validate_structure().is_ok();
+
+    if png.remove_chunk_by_crc(crc, chunk_type).is_err() {
+        let message = match chunk_type {
+            Some(chunk_type) => format!("No `{}` chunk with crc {:#010x}", chunk_type, crc),
+            None => format!("No chunk with crc {:#010x}", crc),
+        };
+
+        fail("not_found", message);
+    }
+
+    if let Some(path) = trailing_to_file {
+        save_trailing_bytes(&mut png, path);
+    }
+
+    write_file(filename, &checked_png_bytes(&png, was_valid, read_options.crc_policy));
+}
+
+// With `force`, the result is written even if it's no longer a valid PNG
+pub fn remove_at(filename: &str, index: usize, force: bool, trailing_to_file: Option<&str>, read_options: &ReadOptions) {
+    let mut png = read_png_from_file(filename, read_options);
+    let was_valid = png.validate_structure().is_ok();
+
+    match remove_chunk_at_guarded(&mut png, index, force) {
+        Ok(_) => {},
+        Err(err) => {
+            fail("remove", format!("Error removing chunk: {}", err));
+        },
+    }
+
+    if let Some(path) = trailing_to_file {
+        save_trailing_bytes(&mut png, path);
+    }
+
+    write_file(filename, &checked_png_bytes(&png, was_valid && !force, read_options.crc_policy));
+}
+
+// Moves the bytes after `IEND` into their own file, so stripping them doesn't lose a payload
+fn save_trailing_bytes(png: &mut Png, path: &str) {
+    let trailing = png.trailing_bytes().to_vec();
+    write_file(path, &trailing);
+    png.set_trailing_bytes(vec![]);
+
+    println!("Saved {} trailing byte(s) to `{}`", trailing.len(), path);
+}
+
+pub fn append_trailing(filename: &str, data_filename: &str, read_options: &ReadOptions) {
+    let mut png = read_png_from_file(filename, read_options);
+    let was_valid = png.validate_structure().is_ok();
+
+    let mut trailing = png.trailing_bytes().to_vec();
+    trailing.extend(read_file(data_filename));
+    png.set_trailing_bytes(trailing);
+
+    write_file_atomic(filename, &checked_png_bytes(&png, was_valid, read_options.crc_policy));
+}
+
+// Writes to stdout when no data file is given
+pub fn extract_trailing(filename: &str, data_filename: Option<&str>, read_options: &ReadOptions) {
+    let png = read_png_from_file(filename, read_options);
+
+    match data_filename {
+        Some(f) => write_file(f, png.trailing_bytes()),
+        None => {
+            if let Err(err) = std::io::stdout().write_all(png.trailing_bytes()) {
+                fail("io", format!("Error writing to stdout: {:?}", err));
+            }
+        }
+    }
+}
+
+/*
+Writes the data of every chunk to `output_dir`, as `<index>_<type>.bin`, or as
+`<type>/<index>.bin` with `by_type`. The index is the chunk's position in the file
+*/
+pub fn extract_all(filename: &str, output_dir: &str, by_type: bool, read_options: &ReadOptions) {
+    let png = read_png_from_file(filename, read_options);
+
+    match extract_chunks(&png, std::path::Path::new(output_dir), by_type) {
+        Ok(count) => println!("Extracted {} chunk(s) to `{}`", count, output_dir),
+        Err(report) => exit_with(report),
+    }
+}
+
+fn extract_chunks(png: &Png, output_dir: &std::path::Path, by_type: bool) -> std::result::Result<usize, ErrorReport> {
+    for (i, chunk) in png.chunks().iter().enumerate() {
+        let path = if by_type {
+            output_dir.join(chunk.chunk_type().to_string()).join(format!("{}.bin", i))
+        } else {
+            output_dir.join(format!("{}_{}.bin", i, chunk.chunk_type()))
+        };
+        let io_error = |err: std::io::Error| ErrorReport::new("io", format!("Error writing `{}`: {:?}", path.display(), err));
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(io_error)?;
+        }
+        fs::write(&path, chunk.data()).map_err(io_error)?;
+    }
+
+    Ok(png.chunks().len())
+}
+
+pub fn find(filename: &str, needle: &str, hex: bool, count_only: bool, read_options: &ReadOptions) {
+    let png = read_png_from_file(filename, read_options);
+
+    let needle = if hex {
+        match from_hex(needle) {
+            Ok(n) => n,
+            Err(err) => {
+                fail("invalid_input", format!("Invalid hex pattern: {}", err));
+            }
+        }
+    } else {
+        needle.as_bytes().to_vec()
+    };
+
+    if needle.is_empty() {
+        fail("invalid_input", "Search pattern is empty".to_string());
+    }
+
+    let matches = find_matches(&png, &needle);
+
+    if count_only {
+        print_count_only(matches.iter().map(|m| m.offsets.len()).sum());
+    }
+
+    for m in matches {
+        println!("Chunk {} {{ type:{} }} at offsets {:?}", m.index, m.chunk_type, m.offsets);
+    }
+}
+
+// Only the bits whose flag is passed are changed, apart from the reserved bit which is always cleared
+pub fn fix_bits(filename: &str, chunk_type: &str, ancillary: bool, private: bool, safe_to_copy: bool, read_options: &ReadOptions) {
+    let mut png = read_png_from_file(filename, read_options);
+    let was_valid = png.validate_structure().is_ok();
+
+    let chunk = match png.chunk_by_type_mut(chunk_type) {
+        Some(c) => c,
+        _ => {
+            fail("not_found", chunk_not_found_message(&png, chunk_type));
+        },
+    };
+
+    let fixed = fix_chunk_type(chunk.chunk_type(), ancillary, private, safe_to_copy);
+    println!("Changed chunk type `{}` to `{}` ({})", chunk.chunk_type(), fixed, fixed.property_string());
+    chunk.set_chunk_type(fixed);
+
+    write_file_atomic(filename, &checked_png_bytes(&png, was_valid, read_options.crc_policy));
+}
+
+/*
+Verifies each file in turn. Reading a file checks its structure, then every crc
+is checked whatever the --crc-policy, and `deep` also checks that the image data
+decompresses and matches its Adler-32
+
+By default every file is checked and the failures are listed at the end, with
+`fail_fast` the first failure ends the run
+
+Either way, fails if any file failed. `progress_json` writes a JSON line per
+file to stderr, for front-ends showing their own progress
+*/
+pub fn verify(filenames: &[&str], deep: bool, fail_fast: bool, progress_json: bool, read_options: &ReadOptions) {
+    let batch = filenames.len() > 1;
+    let mut stderr = std::io::stderr();
+    let progress: Option<&mut dyn Write> = if progress_json { Some(&mut stderr) } else { None };
+
+    let mut failures = run_batch(filenames, fail_fast, progress, |filename| {
+        if batch {
+            println!("{}:", filename);
+        }
+
+        verify_file(filename, deep, read_options)
+    });
+
+    if failures.is_empty() {
+        return;
+    }
+
+    if !batch || fail_fast {
+        exit_with(failures.remove(0).1);
+    }
+
+    let failed = failures.len();
+
+    for (filename, mut report) in failures {
+        report.message = format!("{}: {}", filename, report.message);
+        report.context.push(("file", ContextValue::Text(filename)));
+        print_error(&report);
+    }
+
+    fail("batch", format!("{} of {} files failed verification", failed, filenames.len()));
+}
+
+fn verify_file(filename: &str, deep: bool, read_options: &ReadOptions) -> std::result::Result<(), ErrorReport> {
+    let png = load_png(filename, read_options)?;
+
+    // these are only left after reading with a lenient --crc-policy
+    let crc_errors = png.crc_errors();
+
+    if !crc_errors.is_empty() {
+        let chunks: Vec<String> = crc_errors
+            .iter()
+            .map(|(i, chunk)| format!("chunk {} ({})", i, chunk.chunk_type()))
+            .collect();
+
+        return Err(ErrorReport::new("crc", format!("Invalid crc in {}", chunks.join(", "))));
+    }
+
+    if let Err(err) = png.validate_structure() {
+        return Err(ErrorReport::new("structure", format!("Invalid structure: {}", err)));
+    }
+
+    println!("Structure OK: {} chunks", png.chunks().len());
+
+    if !png.trailing_bytes().is_empty() {
+        eprintln!("Warning: {} trailing byte(s) after IEND", png.trailing_bytes().len());
+    }
+
+    if deep {
+        // an error here is left to `decompressed_image_data` to report
+        match png.image_data_adler32() {
+            Ok((stored, computed)) if stored != computed => {
+                return Err(ErrorReport::new(
+                    "image_data",
+                    format!(
+                        "Error verifying image data: stored Adler-32 {:#010x} doesn't match {:#010x} computed from the data",
+                        stored, computed
+                    ),
+                ));
+            }
+            Ok((stored, _)) => println!("Adler-32 OK: {:#010x}", stored),
+            Err(_) => {}
+        }
+
+        match png.decompressed_image_data() {
+            Ok(data) => {
+                println!("Image data OK: decompresses to {} bytes", data.len());
+
+                if let Some(warning) = image_data_size_warning(&png, data.len()) {
+                    eprintln!("{}", warning);
+                }
+            }
+            Err(err) => {
+                return Err(ErrorReport::new("image_data", format!("Error verifying image data: {}", err)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// A heuristic for truncated or padded image data, since the pixels aren't unfiltered
+fn image_data_size_warning(png: &Png, decompressed_len: usize) -> Option<String> {
+    match png.expected_image_data_len() {
+        Ok(expected) if expected != decompressed_len => Some(format!(
+            "Warning: image data decompresses to {} bytes, but IHDR describes {} bytes",
+            decompressed_len, expected
+        )),
+        Ok(_) => None,
+        Err(err) => Some(format!("Warning: can't check the image data size: {}", err)),
+    }
+}
+
+/*
+Runs `run` for each file and returns the failures, with `fail_fast` no file after the first failure is run
+
+After each file a `progress_line` is written to `progress`, if given
+*/
+fn run_batch<F>(
+    filenames: &[&str],
+    fail_fast: bool,
+    mut progress: Option<&mut dyn Write>,
+    mut run: F,
+) -> Vec<(String, ErrorReport)>
+where
+    F: FnMut(&str) -> std::result::Result<(), ErrorReport>,
+{
+    let mut failures = vec![];
+
+    for (index, filename) in filenames.iter().enumerate() {
+        let result = run(filename);
+
+        if let Some(progress) = progress.as_mut() {
+            // progress is best effort, it mustn't fail the batch
+            let _ = writeln!(progress, "{}", progress_line(filename, result.is_ok(), index + 1, filenames.len()));
+        }
+
+        if let Err(report) = result {
+            failures.push((filename.to_string(), report));
+
+            if fail_fast {
+                break;
+            }
+        }
+    }
+
+    failures
+}
+
+// `index` counts from 1, so the last file has `"index":total`
+fn progress_line(filename: &str, ok: bool, index: usize, total: usize) -> String {
+    format!(
+        "{{\"file\":{},\"status\":\"{}\",\"index\":{},\"total\":{}}}",
+        json_string(filename),
+        if ok { "ok" } else { "error" },
+        index,
+        total
+    )
+}
+
+pub fn datauri(filename: &str, no_prefix: bool, read_options: &ReadOptions) {
+    let png = read_png_from_file(filename, read_options);
+
+    println!("{}", data_uri(&png.as_bytes(), !no_prefix));
+}
+
+// `order` marks the chunks that are out of canonical order, `grouped` collapses runs of one type
+pub fn print(
+    filenames: &[&str],
+    order: bool,
+    grouped: bool,
+    limit: RowLimit,
+    summary_only: bool,
+    read_options: &ReadOptions,
+) {
+    print_batch(filenames, summary_only, read_options, |png| {
+        let lines = if order {
+            render_order_violations(png)
+        } else if grouped {
+            render_grouped(png)
+        } else {
+            png.describe().to_string().lines().map(String::from).collect()
+        };
+
+        // the first line is the `PNG` heading
+        limit_rows(lines, 1, limit, true)
+    });
+}
+
+/*
+Prints `render` for each file, each under its name when there are several
+
+With `summary_only` nothing is printed per file, only the `BatchSummary` of them all
+*/
+fn print_batch<F>(filenames: &[&str], summary_only: bool, read_options: &ReadOptions, render: F)
+where
+    F: Fn(&Png) -> Vec<String>,
+{
+    if summary_only {
+        for line in summarize_files(filenames, read_options).render() {
+            println!("{}", line);
+        }
+
+        return;
+    }
+
+    for filename in filenames {
+        let png = read_png_from_file(filename, read_options);
+
+        if filenames.len() > 1 {
+            println!("{}:", filename);
+        }
+
+        for line in render(&png) {
+            println!("{}", line);
+        }
+    }
+}
+
+// Totals over a batch of files, chunk types are kept in the order they were first seen
+#[derive(Debug, Default, PartialEq)]
+struct BatchSummary {
+    files: usize,
+    chunks: usize,
+    bytes: usize,
+    chunks_by_type: Vec<(String, usize)>,
+}
+
+impl BatchSummary {
+    fn add(&mut self, png: &Png) {
+        self.files += 1;
+        self.chunks += png.chunks().len();
+        self.bytes += png.byte_size();
+
+        for chunk_type in png.chunk_types() {
+            let chunk_type = chunk_type.to_string();
+
+            match self.chunks_by_type.iter_mut().find(|(t, _)| *t == chunk_type) {
+                Some((_, count)) => *count += 1,
+                None => self.chunks_by_type.push((chunk_type, 1)),
+            }
+        }
+    }
+
+    fn render(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Files: {}", self.files),
+            format!("Chunks: {}", self.chunks),
+            format!("Bytes: {}", self.bytes),
+            "Chunks by type:".to_string(),
+        ];
+
+        lines.extend(self.chunks_by_type.iter().map(|(chunk_type, count)| format!("  {} {}", chunk_type, count)));
+
+        lines
+    }
+}
+
+fn summarize_files(filenames: &[&str], read_options: &ReadOptions) -> BatchSummary {
+    let mut summary = BatchSummary::default();
+
+    for filename in filenames {
+        summary.add(&read_png_from_file(filename, read_options));
+    }
+
+    summary
+}
+
+pub fn list(filename: &str, format: OutputFormat, filter: &TypeFilter, limit: RowLimit, read_options: &ReadOptions) {
+    let png = read_png_from_file(filename, read_options);
+
+    // csv keeps its header and leaves out the note about hidden rows, so it still parses
+    let (header_lines, note) = match format {
+        OutputFormat::Text => (0, true),
+        OutputFormat::Csv => (1, false),
+    };
+
+    for line in limit_rows(render_list(&png, format, filter), header_lines, limit, note) {
+        println!("{}", line);
+    }
+}
+
+// Which chunk types `list` shows, rows keep the index the chunk has in the file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypeFilter {
+    // when set, only these types
+    pub only: Option<Vec<String>>,
+    pub exclude: Vec<String>,
+}
+
+impl TypeFilter {
+    fn allows(&self, chunk_type: &str) -> bool {
+        let included = match &self.only {
+            Some(only) => only.iter().any(|t| t == chunk_type),
+            None => true,
+        };
+
+        included && !self.exclude.iter().any(|t| t == chunk_type)
+    }
+}
+
+// Which rows `print` and `list` show
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowLimit {
+    All,
+    First(usize),
+    Last(usize),
+}
+
+// The first `header_lines` are always kept, with `note` a `... (N more)` line stands in for the rest
+fn limit_rows(mut lines: Vec<String>, header_lines: usize, limit: RowLimit, note: bool) -> Vec<String> {
+    let mut rows = lines.split_off(header_lines.min(lines.len()));
+
+    let hidden = match limit {
+        RowLimit::All => 0,
+        RowLimit::First(n) | RowLimit::Last(n) => rows.len().saturating_sub(n),
+    };
+    let note = if note && hidden > 0 { vec![format!("... ({} more)", hidden)] } else { vec![] };
+
+    match limit {
+        RowLimit::All => lines.extend(rows),
+        RowLimit::First(n) => {
+            rows.truncate(n);
+            lines.extend(rows);
+            lines.extend(note);
+        },
+        RowLimit::Last(_) => {
+            lines.extend(note);
+            lines.extend(rows.drain(hidden..));
+        },
+    }
+
+    lines
+}
+
+pub fn count(filename: &str, format: OutputFormat, read_options: &ReadOptions) {
+    let png = read_png_from_file(filename, read_options);
+
+    for line in render_counts(&png, format) {
+        println!("{}", line);
+    }
+}
+
+pub fn info(filenames: &[&str], summary_only: bool, read_options: &ReadOptions) {
+    print_batch(filenames, summary_only, read_options, info::describe);
+}
+
+/*
+Hashes the PNG as pngme would write it back, so base64 or URL input hashes like
+the file itself. With `per_chunk` prints the digest of each chunk's data instead
+*/
+#[cfg(feature = "checksum")]
+pub fn checksum(filename: &str, algorithm: ChecksumAlgorithm, per_chunk: bool, read_options: &ReadOptions) {
+    let png = read_png_from_file(filename, read_options);
+
+    for line in render_checksums(&png, filename, algorithm, per_chunk) {
+        println!("{}", line);
+    }
+}
+
+#[cfg(feature = "checksum")]
+fn render_checksums(png: &Png, filename: &str, algorithm: ChecksumAlgorithm, per_chunk: bool) -> Vec<String> {
+    if !per_chunk {
+        return vec![format!("{}  {}", algorithm.hex_digest(&png.as_bytes()), filename)];
+    }
+
+    png.chunks()
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| format!("{} {} {}", i, chunk.chunk_type(), algorithm.hex_digest(chunk.data())))
+        .collect()
+}
+
+pub fn minify(filename: &str, output_filename: &str, trailing_to_file: Option<&str>, read_options: &ReadOptions) {
+    let mut png = read_png_from_file(filename, read_options);
+    let original_len = png.byte_size();
+
+    if let Some(path) = trailing_to_file {
+        save_trailing_bytes(&mut png, path);
+    }
+
+    let bytes = minify_png(&png).as_bytes();
+
+    if is_same_file(filename, output_filename) {
+        write_file_atomic(output_filename, &bytes);
+    } else {
+        write_file(output_filename, &bytes);
+    }
+
+    println!(
+        "Minified from {} to {} bytes, saved {} bytes",
+        original_len,
+        bytes.len(),
+        original_len.saturating_sub(bytes.len())
+    );
+}
+
+// Replaces any `tEXt` chunks with the same keyword
+pub fn set_text(filename: &str, keyword: &str, text: &str, read_options: &ReadOptions) {
+    let mut png = read_png_from_file(filename, read_options);
+    let was_valid = png.validate_structure().is_ok();
+
+    if let Err(err) = set_text_chunk(&mut png, keyword, text) {
+        fail("invalid_input", format!("Invalid text chunk: {}", err));
+    }
+
+    write_file_atomic(filename, &checked_png_bytes(&png, was_valid, read_options.crc_policy));
+}
+
+fn set_text_chunk(png: &mut Png, keyword: &str, text: &str) -> crate::Result<()> {
+    let chunk = Chunk::text(keyword, text)?;
+
+    png.retain_chunks(|c| {
+        !(c.chunk_type().eq_str("tEXt") && c.keyword_and_text().is_ok_and(|(k, _)| k == keyword))
+    });
+    add_chunk_before_iend(png, chunk)?;
+
+    Ok(())
+}
+
+// The default image in `IDAT` is kept, which is also the first frame unless the APNG says otherwise
+pub fn strip_apng(filename: &str, output_filename: &str, read_options: &ReadOptions) {
+    let mut png = read_png_from_file(filename, read_options);
+    let removed = strip_animation(&mut png);
+
+    if is_same_file(filename, output_filename) {
+        write_file_atomic(output_filename, png.as_bytes().as_slice());
+    } else {
+        write_file(output_filename, png.as_bytes().as_slice());
+    }
+
+    println!("Removed {} animation chunk(s)", removed);
+}
+
+const APNG_CHUNK_TYPES: [&str; 3] = ["acTL", "fcTL", "fdAT"];
+
+// Checks that this build can encode a message and decode it back, for bug reports
+pub fn selftest() {
+    println!("pngme {}", env!("CARGO_PKG_VERSION"));
+
+    match run_selftest(&std::env::temp_dir()) {
+        Ok(()) => println!("PASS"),
+        Err(err) => {
+            println!("FAIL: {}", err);
+            process::exit(1);
+        },
+    }
+}
+
+const SELFTEST_MESSAGE: &str = "pngme selftest message";
+
+/*
+Works in a new directory under `dir` that only this user can enter, creating it
+fails rather than reuse anything already there, such as a planted symlink
+*/
+fn run_selftest(dir: &std::path::Path) -> std::result::Result<(), String> {
+    let work_dir = dir.join(format!(
+        "pngme_selftest_{}_{}",
+        process::id(),
+        TEMP_NAMES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder
+        .create(&work_dir)
+        .map_err(|err| format!("creating `{}`: {}", work_dir.display(), err))?;
+
+    let result = selftest_round_trip(&work_dir.join("selftest.png"));
+    let _ = fs::remove_dir_all(&work_dir);
+
+    result
+}
+
+// 1x1 8 bit grayscale, the zlib stream holds one black pixel after its filter byte
+fn selftest_png() -> Png {
+    let chunk = |chunk_type: &str, data: &[u8]| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec());
+
+    Png::from_chunks(vec![
+        chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+        chunk("IDAT", &[0x78, 0x9c, 0x63, 0x60, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01]),
+        chunk("IEND", &[]),
+    ])
+}
+
+fn selftest_round_trip(path: &std::path::Path) -> std::result::Result<(), String> {
+    let png = selftest_png();
+    File::options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .and_then(|mut f| f.write_all(&png.as_bytes()))
+        .map_err(|err| format!("writing the test image: {}", err))?;
+
+    let parse = |bytes: &[u8]| Png::try_from(bytes).map_err(|err| format!("reading the test image: {}", err));
+
+    let mut png = parse(&fs::read(path).map_err(|err| format!("reading the test image: {}", err))?)?;
+    Chunk::from_type_and_str("ruSt", SELFTEST_MESSAGE)
+        .and_then(|chunk| add_chunk_before_iend(&mut png, chunk))
+        .map_err(|err| format!("encoding: {}", err))?;
+
+    let mut bytes = vec![];
+    png.to_writer_checked(&mut bytes).map_err(|err| format!("encoding: {}", err))?;
+    fs::write(path, bytes).map_err(|err| format!("writing the encoded image: {}", err))?;
+
+    let png = parse(&fs::read(path).map_err(|err| format!("reading the encoded image: {}", err))?)?;
+    let decoded = decode_message(&png, "ruSt", false).map_err(|report| format!("decoding: {}", report.message))?;
+
+    if decoded != SELFTEST_MESSAGE {
+        return Err(format!("decoded `{}` instead of `{}`", decoded, SELFTEST_MESSAGE));
+    }
+
+    Ok(())
+}
+
+fn strip_animation(png: &mut Png) -> usize {
+    png.retain_chunks(|chunk| !APNG_CHUNK_TYPES.iter().any(|t| chunk.chunk_type().eq_str(t)))
+}
+
+/*
+Keeps only what a viewer needs to render the image: `IHDR`, `PLTE` for
+indexed images, `tRNS`, the image data merged into one `IDAT`, and `IEND`
+
+Trailing bytes are dropped
+*/
+fn minify_png(png: &Png) -> Png {
+    let indexed = png
+        .chunk_by_type("IHDR")
+        .and_then(|chunk| chunk.data().get(9).copied())
+        == Some(3);
+
+    let mut chunks = vec![];
+    let mut merged_idat = false;
+
+    for chunk in png.chunks() {
+        match &*chunk.chunk_type().as_str() {
+            "IDAT" if !merged_idat => {
+                chunks.push(Chunk::new(*chunk.chunk_type(), png.image_data()));
+                merged_idat = true;
+            }
+            "IDAT" => {}
+            "PLTE" if !indexed => {}
+            "IHDR" | "PLTE" | "tRNS" | "IEND" => chunks.push(Chunk::new(*chunk.chunk_type(), chunk.data().to_vec())),
+            _ => {}
+        }
+    }
+
+    Png::from_chunks(chunks)
+}
+
+/*
+The chunk types `encode --type-auto` picks from, in order: `msGe` and then
+`msAe` through `msZe`. All are private, ancillary and safe to copy, so
+other tools leave the chunk alone
+
+The first one not in the file is used, so the same file always gets the same type
+*/
+pub fn auto_chunk_type_candidates() -> Vec<ChunkType> {
+    let third_letters = std::iter::once(b'G').chain((b'A'..=b'Z').filter(|&c| c != b'G'));
+
+    third_letters
+        .map(|c| ChunkType::try_from([b'm', b's', c, b'e']).unwrap())
+        .collect()
+}
+
+fn unused_chunk_type(png: &Png) -> Option<ChunkType> {
+    auto_chunk_type_candidates()
+        .into_iter()
+        .find(|candidate| png.chunks().iter().all(|chunk| chunk.chunk_type() != candidate))
+}
+
+// Suggests the ancillary chunk types that are in the file, since those are where messages live
+fn chunk_not_found_message(png: &Png, chunk_type: &str) -> String {
+    let mut present: Vec<String> = vec![];
+
+    for chunk in png.chunks().iter().filter(|chunk| !chunk.chunk_type().is_critical()) {
+        let present_type = chunk.chunk_type().to_string();
+
+        if !present.contains(&present_type) {
+            present.push(present_type);
+        }
+    }
+
+    if present.is_empty() {
+        format!("Chunk type `{}` not found, the file has no ancillary chunks", chunk_type)
+    } else {
+        format!("Chunk type `{}` not found, ancillary chunk types in the file: {}", chunk_type, present.join(", "))
+    }
+}
+
+fn render_order_violations(png: &Png) -> Vec<String> {
+    let violations = png.order_violations();
+
+    png.describe()
+        .to_string()
+        .lines()
+        .enumerate()
+        .map(|(line_index, line)| {
+            // the first line is the `PNG` heading
+            let violation = violations
+                .iter()
+                .find(|(i, _)| line_index == i + 1)
+                .map(|(_, violation)| violation);
+
+            match violation {
+                Some(violation) => format!("{}  <- out of order: {}", line, violation),
+                None => line.to_string(),
+            }
+        })
+        .collect()
+}
+
+fn render_grouped(png: &Png) -> Vec<String> {
+    let mut groups: Vec<(&Chunk, usize, usize)> = vec![];
+
+    for chunk in png.chunks() {
+        match groups.last_mut() {
+            Some((first, count, total)) if first.chunk_type() == chunk.chunk_type() => {
+                *count += 1;
+                *total += chunk.length() as usize;
+            },
+            _ => groups.push((chunk, 1, chunk.length() as usize)),
+        }
+    }
+
+    let mut lines = vec!["PNG".to_string()];
+
+    lines.extend(groups.into_iter().map(|(first, count, total)| {
+        if count == 1 {
+            format!("Chunk {{ type:{}, length:{} }}", first.chunk_type(), first.length())
+        } else {
+            format!("{} \u{d7} {} (total {} bytes)", first.chunk_type(), count, total)
+        }
+    }));
+
+    lines
+}
+
+fn render_list(png: &Png, format: OutputFormat, filter: &TypeFilter) -> Vec<String> {
+    let rows = png
+        .describe()
+        .chunks
+        .into_iter()
+        .enumerate()
+        .filter(|(_, chunk)| filter.allows(&chunk.chunk_type));
+
+    match format {
+        OutputFormat::Text => rows
+            .map(|(i, chunk)| format!("{} {} length:{} crc:{:#010x}", i, chunk.chunk_type, chunk.length, chunk.crc))
+            .collect(),
+        OutputFormat::Csv => std::iter::once("index,type,length,crc,critical,public,safe_to_copy".to_string())
+            .chain(rows.map(|(i, chunk)| {
+                format!(
+                    "{},{},{},{:#010x},{},{},{}",
+                    i, chunk.chunk_type, chunk.length, chunk.crc, chunk.critical, chunk.public, chunk.safe_to_copy
+                )
+            }))
+            .collect(),
+    }
+}
+
+struct ChunkCount {
+    chunk_type: String,
+    count: usize,
+    // on disk, including each chunk's length, type and crc
+    total_bytes: usize,
+}
+
+// Sorted by chunk type, so the output only depends on which chunks there are
+fn chunk_counts(png: &Png) -> Vec<ChunkCount> {
+    let mut counts: Vec<ChunkCount> = vec![];
+
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type().to_string();
+
+        match counts.iter_mut().find(|c| c.chunk_type == chunk_type) {
+            Some(c) => {
+                c.count += 1;
+                c.total_bytes += chunk.total_len();
+            }
+            None => counts.push(ChunkCount {
+                chunk_type,
+                count: 1,
+                total_bytes: chunk.total_len(),
+            }),
+        }
+    }
+
+    counts.sort_by(|a, b| a.chunk_type.cmp(&b.chunk_type));
+
+    counts
+}
+
+fn render_counts(png: &Png, format: OutputFormat) -> Vec<String> {
+    let counts = chunk_counts(png).into_iter();
+
+    match format {
+        OutputFormat::Text => counts
+            .map(|c| format!("{}: {} chunk(s), {} bytes", c.chunk_type, c.count, c.total_bytes))
+            .collect(),
+        OutputFormat::Csv => std::iter::once("type,count,total_bytes".to_string())
+            .chain(counts.map(|c| format!("{},{},{}", c.chunk_type, c.count, c.total_bytes)))
+            .collect(),
+    }
+}
+
+fn render_removed_chunks(removed: &[(usize, Chunk)]) -> Vec<String> {
+    let mut lines: Vec<String> = removed
+        .iter()
+        .map(|(i, chunk)| format!("Removed chunk {} {{ type:{}, length:{} }}", i, chunk.chunk_type(), chunk.length()))
+        .collect();
+
+    lines.push(format!("Removed {} chunk(s)", removed.len()));
+
+    lines
+}
+
+const DATA_URI_PREFIX: &str = "data:image/png;base64,";
+
+fn data_uri(bytes: &[u8], prefix: bool) -> String {
+    use base64::Engine;
+
+    let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+
+    if prefix {
+        format!("{}{}", DATA_URI_PREFIX, encoded)
+    } else {
+        encoded
+    }
+}
+
+fn fix_chunk_type(chunk_type: &ChunkType, ancillary: bool, private: bool, safe_to_copy: bool) -> ChunkType {
+    let mut fixed = chunk_type.set_reserved_bit_valid(true);
+
+    if ancillary {
+        fixed = fixed.set_critical(false);
+    }
+
+    if private {
+        fixed = fixed.set_public(false);
+    }
+
+    if safe_to_copy {
+        fixed = fixed.set_safe_to_copy(true);
+    }
+
+    fixed
+}
+
+struct FindMatch {
+    index: usize,
+    chunk_type: String,
+    // within the chunk data
+    offsets: Vec<usize>,
+}
+
+// For `--count-only`, which exits with an error when nothing matched so shell conditionals work
+fn print_count_only(count: usize) -> ! {
+    println!("{}", count);
+    process::exit(count_only_exit_code(count));
+}
+
+fn count_only_exit_code(count: usize) -> i32 {
+    if count > 0 {
+        0
+    } else {
+        1
+    }
+}
+
+fn find_matches(png: &Png, needle: &[u8]) -> Vec<FindMatch> {
+    png.chunks()
+        .iter()
+        .enumerate()
+        .filter_map(|(index, chunk)| {
+            let offsets: Vec<usize> = chunk
+                .data()
+                .windows(needle.len())
+                .enumerate()
+                .filter(|(_, window)| *window == needle)
+                .map(|(offset, _)| offset)
+                .collect();
+
+            if offsets.is_empty() {
+                return None;
+            }
+
+            Some(FindMatch {
+                index,
+                chunk_type: chunk.chunk_type().to_string(),
+                offsets,
+            })
+        })
+        .collect()
+}
+
+fn chunk_size_warning(len: usize, max_chunk_size: usize) -> Option<String> {
+    if len <= max_chunk_size {
+        return None;
+    }
+
+    Some(format!(
+        "Warning: the chunk is {} bytes, some viewers reject chunks larger than {} bytes. Consider compressing or splitting the message.",
+        len, max_chunk_size
+    ))
+}
+
+// Removing IHDR or IEND leaves a broken PNG, so it has to be forced
+fn remove_chunk_at_guarded(png: &mut Png, index: usize, force: bool) -> crate::Result<Chunk> {
+    if let Some(chunk) = png.chunks().get(index) {
+        let chunk_type = chunk.chunk_type().as_str();
+
+        if !force && (chunk_type == "IHDR" || chunk_type == "IEND") {
+            return Err("refusing to remove IHDR or IEND without --force");
+        }
+    }
+
+    png.remove_chunk_at(index)
+}
+
+// One line per ancillary chunk, as text when the data is valid UTF-8 and as hex otherwise
+fn render_ancillary_chunks(png: &Png) -> Vec<String> {
+    png.chunks()
+        .iter()
+        .filter(|chunk| !chunk.chunk_type().is_critical())
+        .map(|chunk| match std::str::from_utf8(chunk.data()) {
+            Ok(s) => format!("{}: `{}`", chunk.chunk_type(), s),
+            Err(_) => format!("{} (hex): {}", chunk.chunk_type(), to_hex(chunk.data())),
+        })
+        .collect()
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// Accepts pairs of hex digits, optionally separated by whitespace
+fn from_hex(s: &str) -> crate::Result<Vec<u8>> {
+    let digits: Vec<char> = s.chars().filter(|c| !c.is_whitespace()).collect();
+
+    let pairs = digits.chunks_exact(2);
+
+    if !pairs.remainder().is_empty() {
+        return Err("odd number of hex digits");
+    }
+
+    pairs
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).map_err(|_| "invalid hex digit")
+        })
+        .collect()
+}
+
+// A failed command, printed as `message` or as a JSON object with --json-errors
+struct ErrorReport {
+    kind: &'static str,
+    message: String,
+    // extra JSON fields, e.g. where a bad crc was found
+    context: Vec<(&'static str, ContextValue)>,
+}
+
+enum ContextValue {
+    Number(usize),
+    Text(String),
+}
+
+impl ErrorReport {
+    fn new(kind: &'static str, message: String) -> ErrorReport {
+        ErrorReport {
+            kind,
+            message,
+            context: Vec::new(),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut fields = vec![
+            format!("\"kind\":{}", json_string(self.kind)),
+            format!("\"message\":{}", json_string(&self.message)),
+        ];
+
+        for (key, value) in &self.context {
+            let value = match value {
+                ContextValue::Number(n) => n.to_string(),
+                ContextValue::Text(s) => json_string(s),
+            };
+
+            fields.push(format!("{}:{}", json_string(key), value));
+        }
+
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+fn print_error(report: &ErrorReport) {
+    if JSON_ERRORS.load(Ordering::Relaxed) {
+        eprintln!("{}", report.to_json());
+    } else {
+        eprintln!("{}", report.message);
+    }
+}
+
+fn exit_with(report: ErrorReport) -> ! {
+    print_error(&report);
+    process::exit(1);
+}
+
+fn fail(kind: &'static str, message: String) -> ! {
+    exit_with(ErrorReport::new(kind, message))
+}
+
+/*
+Points at the chunk parsing failed on, unless the signature itself is invalid,
+and at the first chunk that failed its crc check if that is why
+*/
+fn parse_error_report(contents: &[u8], raw: bool, crc_policy: CrcPolicy, err: crate::Error) -> ErrorReport {
+    let mut report = ErrorReport::new("parse", format!("Error parsing PNG {:?}", err));
+
+    let body_failure = if raw {
+        Png::parse_raw_body(contents, crc_policy, ParseLimits::default()).err()
+    } else if Png::check_signature(contents).is_ok() {
+        Png::parse_body(&contents[Png::STANDARD_HEADER.len()..], crc_policy, ParseLimits::default()).err()
+    } else {
+        None
+    };
+
+    if let Some(failure) = body_failure {
+        report.message = format!("Error parsing PNG {:?} at offset {}", failure.error, failure.offset);
+        report.context = vec![("offset", ContextValue::Number(failure.offset))];
+    }
+
+    let png = match parse_png(contents, raw, CrcPolicy::None) {
+        Ok(png) => png,
+        Err(_) => return report,
+    };
+
+    let mut offset = if raw { 0 } else { Png::STANDARD_HEADER.len() };
+
+    for chunk in png.chunks() {
+        if crc_policy.should_validate(chunk) && !chunk.has_valid_crc() {
+            let expected = Chunk::compute_crc(chunk.chunk_type(), chunk.data());
+
+            if chunk.crc_matches_with_reserved_bit_cleared() {
+                report.message = format!("{} ({})", report.message, RESERVED_BIT_CRC_HINT);
+            }
+
+            report.kind = "crc";
+            report.context = vec![
+                ("chunk_type", ContextValue::Text(chunk.chunk_type().to_string())),
+                ("offset", ContextValue::Number(offset)),
+                ("expected_crc", ContextValue::Text(format!("{:#010x}", expected))),
+                ("actual_crc", ContextValue::Text(format!("{:#010x}", chunk.crc()))),
+            ];
+
+            break;
+        }
+
+        offset += chunk.total_len();
+    }
+
+    report
+}
+
+fn read_png_from_file(filename: &str, read_options: &ReadOptions) -> Png {
+    match load_png(filename, read_options) {
+        Ok(png) => png,
+        Err(report) => exit_with(report),
+    }
+}
+
+// Like `read_png_from_file`, but leaves it to the caller whether a failure is fatal
+fn load_png(filename: &str, read_options: &ReadOptions) -> std::result::Result<Png, ErrorReport> {
+    #[cfg(feature = "network")]
+    {
+        if is_url(filename) {
+            let contents = fetch_url(filename, read_options)
+                .map_err(|err| ErrorReport::new("network", format!("Error fetching `{}`: {}", filename, err)))?;
+
+            return read_png(contents.as_slice(), filename, read_options);
+        }
+    }
+
+    if filename == "-" {
+        return read_png(std::io::stdin().lock(), filename, read_options);
+    }
+
+    let f = File::open(filename)
+        .map_err(|err| ErrorReport::new("io", format!("Error opening file `{}`: {:?}", filename, err)))?;
+
+    read_png(f, filename, read_options)
+}
+
+// Everything after opening the input, `filename` is only used in error messages
+fn read_png<R: Read>(mut reader: R, filename: &str, read_options: &ReadOptions) -> std::result::Result<Png, ErrorReport> {
+    let mut contents = Vec::new();
+
+    if let Err(err) = reader.read_to_end(&mut contents) {
+        return Err(ErrorReport::new("io", format!("Error reading file `{}`: {:?}", filename, err)));
+    }
+
+    let contents = if read_options.base64 || contents.starts_with(DATA_URI_PREFIX.as_bytes()) {
+        decode_base64(&contents)
+            .map_err(|err| ErrorReport::new("decode", format!("Error decoding base64 file `{}`: {}", filename, err)))?
+    } else {
+        contents
+    };
+
+    let contents = decompress_if_gzip(contents, gzip_limit(filename, read_options))
+        .map_err(|err| ErrorReport::new("decode", format!("Error decompressing file `{}`: {}", filename, err)))?;
+
+    // with --assume-raw, input that does start with the signature is still read as usual
+    let raw = read_options.assume_raw && !Png::has_valid_signature(&contents);
+
+    parse_png(&contents, raw, read_options.crc_policy)
+        .map_err(|err| parse_error_report(&contents, raw, read_options.crc_policy, err))
+}
+
+fn parse_png(contents: &[u8], raw: bool, crc_policy: CrcPolicy) -> crate::Result<Png> {
+    if raw {
+        Png::try_from_raw_with_policy(contents, crc_policy)
+    } else {
+        Png::try_from_with_policy(contents, crc_policy)
+    }
+}
+
+// Accepts bare base64 or a data URI, ignoring whitespace such as line breaks
+fn decode_base64(contents: &[u8]) -> crate::Result<Vec<u8>> {
+    use base64::Engine;
+
+    let contents = contents.strip_prefix(DATA_URI_PREFIX.as_bytes()).unwrap_or(contents);
+    let contents: Vec<u8> = contents.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+
+    match base64::engine::general_purpose::STANDARD.decode(contents) {
+        Ok(decoded) => Ok(decoded),
+        Err(_) => Err("invalid base64"),
+    }
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// Gzip input may inflate to at most this many bytes, so a small file can't expand without bound
+const MAX_GZIP_INFLATED: usize = 256 * 1024 * 1024;
+
+// A URL is held to --max-download after inflating as well
+#[cfg_attr(not(feature = "network"), allow(unused_variables))]
+fn gzip_limit(filename: &str, read_options: &ReadOptions) -> usize {
+    #[cfg(feature = "network")]
+    {
+        if is_url(filename) {
+            return read_options.max_download;
+        }
+    }
+
+    MAX_GZIP_INFLATED
+}
+
+// Inflates gzip compressed input to at most `limit` bytes, anything else is passed through untouched
+fn decompress_if_gzip(contents: Vec<u8>, limit: usize) -> std::result::Result<Vec<u8>, String> {
+    if !contents.starts_with(&GZIP_MAGIC) {
+        return Ok(contents);
+    }
+
+    // one byte over the limit tells a stream that fits exactly from one that doesn't
+    let mut decoder = flate2::read::GzDecoder::new(&contents[..]).take(limit as u64 + 1);
+    let mut buffer = Vec::new();
+    decoder.read_to_end(&mut buffer).map_err(|err| format!("{:?}", err))?;
+
+    if buffer.len() > limit {
+        return Err(format!("inflates to more than {} bytes", limit));
+    }
+
+    Ok(buffer)
+}
+
+#[cfg(feature = "network")]
+fn is_url(filename: &str) -> bool {
+    filename.starts_with("http://") || filename.starts_with("https://")
+}
+
+// Errors instead of reading more than `max_download` bytes
+#[cfg(feature = "network")]
+fn fetch_url(url: &str, read_options: &ReadOptions) -> std::result::Result<Vec<u8>, String> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(read_options.download_timeout)
+        .timeout_read(read_options.download_timeout)
+        .build();
+
+    let response = agent.get(url).call().map_err(|err| err.to_string())?;
+    let too_large = format!("response is larger than {} bytes", read_options.max_download);
+
+    // fails early when the server is honest about the size
+    if let Some(len) = response.header("Content-Length").and_then(|len| len.parse::<usize>().ok()) {
+        if len > read_options.max_download {
+            return Err(too_large);
+        }
+    }
+
+    let mut buffer = Vec::new();
+    response
+        .into_reader()
+        .take(read_options.max_download as u64 + 1)
+        .read_to_end(&mut buffer)
+        .map_err(|err| err.to_string())?;
+
+    if buffer.len() > read_options.max_download {
+        return Err(too_large);
+    }
+
+    Ok(buffer)
+}
+
+fn read_file(filename: &str) -> Vec<u8> {
+    match try_read_file(filename) {
+        Ok(buffer) => buffer,
+        Err(report) => exit_with(report),
+    }
+}
+
+// `-` reads from stdin
+fn try_read_file(filename: &str) -> std::result::Result<Vec<u8>, ErrorReport> {
+    let mut buffer = Vec::new();
+
+    if filename == "-" {
+        return match std::io::stdin().read_to_end(&mut buffer) {
+            Ok(_) => Ok(buffer),
+            Err(err) => Err(ErrorReport::new("io", format!("Error reading stdin: {:?}", err))),
+        };
+    }
+
+    let mut f = File::open(filename)
+        .map_err(|err| ErrorReport::new("io", format!("Error opening file `{}`: {:?}", filename, err)))?;
+
+    match f.read_to_end(&mut buffer) {
+        Ok(_) => Ok(buffer),
+        Err(err) => Err(ErrorReport::new("io", format!("Error reading file `{}`: {:?}", filename, err))),
+    }
+}
+
+/*
+`-` reads the input from stdin, so there's no file to write the result back to,
+commands that default to overwriting FILE need an OUTPUT_FILE then
+*/
+fn check_output_filename(filename: &str) -> std::result::Result<(), ErrorReport> {
+    if filename == "-" {
+        return Err(ErrorReport::new(
+            "invalid_input",
+            "Can't write the result back to `-` (stdin), give an output file name".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn write_file(filename: &str, data: &[u8]) {
+    if let Err(report) = check_output_filename(filename) {
+        exit_with(report);
+    }
+
+    let mtime = overwritten_mtime(filename);
+
+    let mut f = match File::create(filename) {
+        Ok(f) => f,
+        Err(err) => {
+            fail("io", format!("Error creatubg file `{}`: {:?}", filename, err));
+        }
+    };
+
+    match f.write_all(data) {
+        Ok(_) => {},
+        Err(err) => {
+            fail("io", format!("Error writing to file `{}`: {:?}", filename, err));
+        }
+    }
+
+    if let Some(mtime) = mtime {
+        restore_mtime(filename, mtime);
+    }
+}
+
+// Fails if `filename` exists, checked as part of creating it so nothing can slip in between
+fn write_new_file(filename: &str, data: &[u8]) -> std::result::Result<(), ErrorReport> {
+    check_output_filename(filename)?;
+
+    let mut f = File::options()
+        .write(true)
+        .create_new(true)
+        .open(filename)
+        .map_err(|err| match err.kind() {
+            std::io::ErrorKind::AlreadyExists => ErrorReport::new("exists", format!("Output file `{}` already exists", filename)),
+            _ => ErrorReport::new("io", format!("Error creating file `{}`: {:?}", filename, err)),
+        })?;
+
+    f.write_all(data)
+        .map_err(|err| ErrorReport::new("io", format!("Error writing to file `{}`: {:?}", filename, err)))
+}
+
+// With --preserve-mtime, the modification time to put back after overwriting an existing file
+fn overwritten_mtime(filename: &str) -> Option<SystemTime> {
+    if !PRESERVE_MTIME.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    fs::metadata(filename).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn restore_mtime(filename: &str, mtime: SystemTime) {
+    let result = File::options()
+        .write(true)
+        .open(filename)
+        .and_then(|f| f.set_modified(mtime));
+
+    if let Err(err) = result {
+        fail("io", format!("Error restoring modification time of `{}`: {:?}", filename, err));
+    }
+}
+
+/*
+Calls `on_change` once per burst of changes to `filename`, until it returns false
+
+The directory is watched rather than the file, so that replacing the file
+with a rename is noticed as well
+*/
+#[cfg(feature = "watch")]
+fn watch_file(filename: &str, debounce: Duration, mut on_change: impl FnMut() -> bool) -> notify::Result<()> {
+    use notify::{RecursiveMode, Watcher};
+    use std::sync::mpsc;
+
+    let path = fs::canonicalize(filename)?;
+    let name = path.file_name().map(|name| name.to_os_string());
+    let dir = path.parent().unwrap_or(&path);
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+    // reading the file to decode it shows up as access events
+    let is_change = |event: notify::Result<notify::Event>| match event {
+        Ok(event) => !event.kind.is_access() && event.paths.iter().any(|p| p.file_name() == name.as_deref()),
+        Err(_) => false,
+    };
+
+    while let Ok(event) = rx.recv() {
+        if !is_change(event) {
+            continue;
+        }
+
+        while rx.recv_timeout(debounce).is_ok() {}
+
+        if !on_change() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Both paths must exist for them to be considered the same file
+fn is_same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// Numbers the temporary files and directories this process creates, see `write_file_atomic`
+static TEMP_NAMES: AtomicUsize = AtomicUsize::new(0);
+
+/*
+Writes to a temporary file next to `filename` and renames it into place,
+so a failed write never leaves a partially written file behind
+
+A symlink is followed so the file it points to is replaced rather than the link,
+and the replacement keeps the original file's permissions. The temporary name
+includes the pid and a counter, so concurrent runs don't write to the same one
+*/
+fn write_file_atomic(filename: &str, data: &[u8]) {
+    if let Err(report) = check_output_filename(filename) {
+        exit_with(report);
+    }
+
+    let target = match fs::canonicalize(filename) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => filename.to_string(),
+    };
+    let permissions = fs::metadata(&target).map(|metadata| metadata.permissions()).ok();
+    let mtime = overwritten_mtime(&target);
+
+    let tmp_filename = format!(
+        "{}.{}.{}.pngme.tmp",
+        target,
+        process::id(),
+        TEMP_NAMES.fetch_add(1, Ordering::Relaxed)
+    );
+
+    if let Err(report) = write_new_file(&tmp_filename, data) {
+        // an existing file of that name isn't ours to remove
+        if report.kind != "exists" {
+            let _ = fs::remove_file(&tmp_filename);
+        }
+
+        exit_with(report);
+    }
+
+    if let Some(permissions) = permissions {
+        if let Err(err) = fs::set_permissions(&tmp_filename, permissions) {
+            let _ = fs::remove_file(&tmp_filename);
+            fail("io", format!("Error copying the permissions of `{}`: {:?}", target, err));
+        }
+    }
+
+    if let Err(err) = fs::rename(&tmp_filename, &target) {
+        let _ = fs::remove_file(&tmp_filename);
+        fail("io", format!("Error renaming `{}` to `{}`: {:?}", tmp_filename, target, err));
+    }
+
+    if let Some(mtime) = mtime {
+        restore_mtime(&target, mtime);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn testing_png() -> Png {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"I am the first chunk".to_vec()),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"I am the secret".to_vec()),
+        ];
+
+        Png::from_chunks(chunks)
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pngme_{}_{}", process::id(), name))
+    }
+
+    #[test]
+    fn test_append_and_extract_trailing() {
+        let png_path = temp_path("trailing.png");
+        let payload_path = temp_path("trailing_payload.bin");
+        let extracted_path = temp_path("trailing_extracted.bin");
+        let (png_file, payload_file, extracted_file) = (
+            png_path.to_str().unwrap(),
+            payload_path.to_str().unwrap(),
+            extracted_path.to_str().unwrap(),
+        );
+
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]));
+        write_file(png_file, &png.as_bytes());
+        write_file(payload_file, &[0, 1, 2, 254, 255]);
+
+        append_trailing(png_file, payload_file, &ReadOptions::default());
+        extract_trailing(png_file, Some(extracted_file), &ReadOptions::default());
+
+        let extracted = read_file(extracted_file);
+        let round_tripped = read_png_from_file(png_file, &ReadOptions::default());
+        for path in [&png_path, &payload_path, &extracted_path].iter() {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(extracted, vec![0, 1, 2, 254, 255]);
+        assert_eq!(round_tripped.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_remove_trailing_to_file() {
+        let png_path = temp_path("trailing_to_file.png");
+        let saved_path = temp_path("trailing_to_file.bin");
+        let (png_file, saved_file) = (png_path.to_str().unwrap(), saved_path.to_str().unwrap());
+
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]));
+        png.set_trailing_bytes(b"hidden payload".to_vec());
+        write_file(png_file, &png.as_bytes());
+
+        remove(png_file, "ruSt", Some(saved_file), &ReadOptions::default());
+
+        let saved = read_file(saved_file);
+        let rewritten = read_png_from_file(png_file, &ReadOptions::default());
+        fs::remove_file(&png_path).unwrap();
+        fs::remove_file(&saved_path).unwrap();
+
+        assert_eq!(saved, b"hidden payload".to_vec());
+        assert!(rewritten.trailing_bytes().is_empty());
+        assert!(!rewritten.contains_chunk_type("ruSt"));
+    }
+
+    #[test]
+    fn test_extract_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"second".to_vec()));
+
+        let by_type_dir = temp_path("extract_by_type");
+        let flat_dir = temp_path("extract_flat");
+        let by_type = extract_chunks(&png, &by_type_dir, true).ok();
+        let flat = extract_chunks(&png, &flat_dir, false).ok();
+
+        let read = |path: PathBuf| fs::read(path).unwrap();
+        let frst = read(by_type_dir.join("FrSt").join("0.bin"));
+        let rust = (read(by_type_dir.join("ruSt").join("1.bin")), read(by_type_dir.join("ruSt").join("2.bin")));
+        let mut rust_files: Vec<_> = fs::read_dir(by_type_dir.join("ruSt")).unwrap().map(|e| e.unwrap().file_name()).collect();
+        rust_files.sort();
+        let flat_rust = read(flat_dir.join("2_ruSt.bin"));
+
+        fs::remove_dir_all(&by_type_dir).unwrap();
+        fs::remove_dir_all(&flat_dir).unwrap();
+
+        assert_eq!((by_type, flat), (Some(3), Some(3)));
+        assert_eq!(frst, png.chunks()[0].data());
+        assert_eq!(rust, (b"I am the secret".to_vec(), b"second".to_vec()));
+        assert_eq!(rust_files, vec!["1.bin", "2.bin"]);
+        assert_eq!(flat_rust, b"second".to_vec());
+    }
+
+    #[test]
+    fn test_verify_checks_structure() {
+        let path = temp_path("verify_structure.png");
+        let filename = path.to_str().unwrap();
+
+        write_file(filename, &selftest_png().as_bytes());
+        let valid = verify_file(filename, false, &ReadOptions::default());
+
+        let mut png = selftest_png();
+        png.remove_all_chunks("IEND");
+        write_file(filename, &png.as_bytes());
+        let missing_iend = verify_file(filename, false, &ReadOptions::default());
+
+        write_file(filename, &testing_png().as_bytes());
+        let no_ihdr = verify_file(filename, false, &ReadOptions::default());
+        fs::remove_file(&path).unwrap();
+
+        assert!(valid.is_ok());
+        let report = missing_iend.err().unwrap();
+        assert_eq!(report.kind, "structure");
+        assert_eq!(report.message, "Invalid structure: IEND must be the last chunk");
+        assert_eq!(no_ihdr.err().unwrap().message, "Invalid structure: IHDR must be the first chunk");
+    }
+
+    #[test]
+    fn test_verify_deep_catches_tampered_adler32() {
+        let path = temp_path("tampered_adler32.png");
+        let filename = path.to_str().unwrap();
+        let chunk = |chunk_type: &str, data: &[u8]| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec());
+
+        // one black pixel, with the last byte of the Adler-32 changed from 0x01
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("IDAT", &[0x78, 0x9c, 0x63, 0x60, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02]),
+            chunk("IEND", &[]),
+        ]);
+        write_file(filename, &png.as_bytes());
+
+        let shallow = verify_file(filename, false, &ReadOptions::default());
+        let deep = verify_file(filename, true, &ReadOptions::default());
+        fs::remove_file(&path).unwrap();
+
+        assert!(shallow.is_ok());
+        let report = deep.err().unwrap();
+        assert_eq!(report.kind, "image_data");
+        assert_eq!(
+            report.message,
+            "Error verifying image data: stored Adler-32 0x00020002 doesn't match 0x00020001 computed from the data"
+        );
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn test_render_checksums() {
+        use sha2::Digest;
+
+        let png = testing_png();
+        let sha256 = |bytes: &[u8]| format!("{:x}", sha2::Sha256::digest(bytes));
+
+        assert_eq!(
+            render_checksums(&png, "in.png", ChecksumAlgorithm::Sha256, false),
+            vec![format!("{}  in.png", sha256(&png.as_bytes()))]
+        );
+        assert_eq!(
+            render_checksums(&png, "in.png", ChecksumAlgorithm::Sha256, true),
+            vec![
+                format!("0 FrSt {}", sha256(png.chunks()[0].data())),
+                format!("1 ruSt {}", sha256(b"I am the secret")),
+            ]
+        );
+        // the well known digest of no bytes
+        assert_eq!(
+            ChecksumAlgorithm::Sha256.hex_digest(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn test_summarize_files() {
+        let paths = [temp_path("summary_1.png"), temp_path("summary_2.png")];
+        let filenames: Vec<&str> = paths.iter().map(|p| p.to_str().unwrap()).collect();
+
+        let mut second = testing_png();
+        second.append_chunk(Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"Comment\0hi".to_vec()));
+
+        write_file(filenames[0], &testing_png().as_bytes());
+        write_file(filenames[1], &second.as_bytes());
+
+        let summary = summarize_files(&filenames, &ReadOptions::default());
+
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(summary.files, 2);
+        assert_eq!(summary.chunks, 5);
+        assert_eq!(summary.bytes, testing_png().byte_size() + second.byte_size());
+        assert_eq!(
+            summary.chunks_by_type,
+            vec![("FrSt".to_string(), 2), ("ruSt".to_string(), 2), ("tEXt".to_string(), 1)]
+        );
+        assert_eq!(
+            summary.render(),
+            vec![
+                "Files: 2".to_string(),
+                "Chunks: 5".to_string(),
+                format!("Bytes: {}", summary.bytes),
+                "Chunks by type:".to_string(),
+                "  FrSt 2".to_string(),
+                "  ruSt 2".to_string(),
+                "  tEXt 1".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_batch_progress_json() {
+        let paths = [temp_path("progress_1.png"), temp_path("progress_2.png"), temp_path("progress_3.png")];
+        let filenames: Vec<&str> = paths.iter().map(|p| p.to_str().unwrap()).collect();
+
+        write_file(filenames[0], &selftest_png().as_bytes());
+        write_file(filenames[1], b"not a png");
+        write_file(filenames[2], &selftest_png().as_bytes());
+
+        let mut progress = vec![];
+        let failures = run_batch(&filenames, false, Some(&mut progress), |f| {
+            verify_file(f, false, &ReadOptions::default())
+        });
+
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(failures.len(), 1);
+
+        let lines: Vec<String> = String::from_utf8(progress).unwrap().lines().map(String::from).collect();
+        let expected: Vec<String> = filenames
+            .iter()
+            .zip(&["ok", "error", "ok"])
+            .enumerate()
+            .map(|(i, (f, status))| {
+                format!("{{\"file\":{},\"status\":\"{}\",\"index\":{},\"total\":3}}", json_string(f), status, i + 1)
+            })
+            .collect();
+
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn test_run_batch_fail_fast_and_collect_all() {
+        let paths = [temp_path("batch_1.png"), temp_path("batch_2.png"), temp_path("batch_3.png")];
+        let filenames: Vec<&str> = paths.iter().map(|p| p.to_str().unwrap()).collect();
+
+        write_file(filenames[0], &selftest_png().as_bytes());
+        write_file(filenames[1], b"not a png");
+        write_file(filenames[2], &selftest_png().as_bytes());
+
+        let mut checked = vec![];
+        let collected = run_batch(&filenames, false, None, |f| {
+            checked.push(f.to_string());
+            verify_file(f, false, &ReadOptions::default())
+        });
+
+        let mut checked_fast = vec![];
+        let fast = run_batch(&filenames, true, None, |f| {
+            checked_fast.push(f.to_string());
+            verify_file(f, false, &ReadOptions::default())
+        });
+
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(checked.len(), 3);
+        assert_eq!(checked_fast.len(), 2);
+
+        for failures in [collected, fast] {
+            assert_eq!(failures.len(), 1);
+            assert_eq!(failures[0].0, filenames[1]);
+            assert_eq!(failures[0].1.kind, "parse");
+        }
+    }
+
+    #[test]
+    fn test_chunk_at_offset() {
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"I am hidden".to_vec());
+
+        let mut contents = b"garbage before the chunk".to_vec();
+        let offset = contents.len();
+        contents.extend(chunk.as_bytes());
+        contents.extend_from_slice(b"garbage after");
+
+        assert_eq!(chunk_at_offset(&contents, offset), Ok(chunk));
+        assert!(chunk_at_offset(&contents, offset + 1).is_err());
+        assert_eq!(chunk_at_offset(&contents, contents.len() + 1), Err("offset is past the end of the file"));
+    }
+
+    #[test]
+    fn test_set_text_chunk() {
+        let mut png = testing_png();
+        set_text_chunk(&mut png, "Comment", "first").unwrap();
+        set_text_chunk(&mut png, "Author", "someone").unwrap();
+        set_text_chunk(&mut png, "Comment", "second").unwrap();
+
+        let texts: Vec<(String, String)> = png
+            .chunks()
+            .iter()
+            .filter_map(|c| c.keyword_and_text().ok())
+            .collect();
+
+        assert_eq!(texts, vec![
+            ("Author".to_string(), "someone".to_string()),
+            ("Comment".to_string(), "second".to_string()),
+        ]);
+        assert!(set_text_chunk(&mut png, "", "text").is_err());
+    }
+
+    #[test]
+    fn test_strip_animation() {
+        let chunk = |chunk_type: &str, data: &[u8]| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec());
+
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]),
+            chunk("acTL", &[0, 0, 0, 2, 0, 0, 0, 0]),
+            chunk("fcTL", &[0; 26]),
+            chunk("IDAT", b"first frame"),
+            chunk("fcTL", &[0; 26]),
+            chunk("fdAT", b"second frame"),
+            chunk("IEND", &[]),
+        ]);
+
+        assert_eq!(strip_animation(&mut png), 4);
+
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        let types: Vec<String> = reparsed.chunk_types().map(|c| c.to_string()).collect();
+
+        assert_eq!(types, vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(reparsed.image_data(), b"first frame");
+        assert!(reparsed.order_violations().is_empty());
+        assert_eq!(strip_animation(&mut png), 0);
+    }
+
+    #[test]
+    fn test_minify_png() {
+        let chunk = |chunk_type: &str, data: &[u8]| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec());
+
+        let ihdr = [0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0];
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &ihdr),
+            chunk("gAMA", &[0, 0, 177, 143]),
+            chunk("PLTE", &[0; 6]),
+            chunk("tRNS", &[0, 1, 0, 2, 0, 3]),
+            chunk("IDAT", b"first"),
+            chunk("tEXt", b"Comment\0hello"),
+            chunk("IDAT", b"second"),
+            chunk("ruSt", b"I am the secret"),
+            chunk("IEND", &[]),
+        ]);
+        png.set_trailing_bytes(b"trailing".to_vec());
+
+        let minified = minify_png(&png);
+        let bytes = minified.as_bytes();
+        let reparsed = Png::try_from_with_policy(&bytes, CrcPolicy::All).unwrap();
+
+        let types: Vec<String> = reparsed.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "tRNS", "IDAT", "IEND"]);
+        assert_eq!(reparsed.image_data(), b"firstsecond");
+        assert!(reparsed.trailing_bytes().is_empty());
+        assert!(bytes.len() < png.as_bytes().len());
+
+        // the palette is kept for indexed images
+        let mut indexed_ihdr = ihdr;
+        indexed_ihdr[9] = 3;
+        let png = Png::from_chunks(vec![chunk("IHDR", &indexed_ihdr), chunk("PLTE", &[0; 6]), chunk("IEND", &[])]);
+        assert_eq!(minify_png(&png).chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_read_raw_chunk_stream() {
+        let path = temp_path("raw_chunks.bin");
+        let filename = path.to_str().unwrap();
+
+        let bytes = testing_png().as_bytes();
+        write_file(filename, &bytes[Png::STANDARD_HEADER.len()..]);
+
+        let read_options = ReadOptions {
+            assume_raw: true,
+            ..ReadOptions::default()
+        };
+        let png = read_png_from_file(filename, &read_options);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_data_uri() {
+        use base64::Engine;
+
+        let bytes = testing_png().as_bytes();
+
+        let uri = data_uri(&bytes, true);
+        assert!(uri.starts_with("data:image/png;base64,iVBORw0KGgo"));
+
+        let encoded = data_uri(&bytes, false);
+        assert_eq!(uri, format!("{}{}", DATA_URI_PREFIX, encoded));
+
+        let decoded = base64::engine::general_purpose::STANDARD.decode(encoded).unwrap();
+        assert_eq!(decoded, bytes);
+    }
+
+    #[test]
+    fn test_read_base64_png() {
+        let bytes = testing_png().as_bytes();
+
+        let uri_path = temp_path("base64_uri.txt");
+        write_file(uri_path.to_str().unwrap(), format!("{}\n", data_uri(&bytes, true)).as_bytes());
+        let from_uri = read_png_from_file(uri_path.to_str().unwrap(), &ReadOptions::default());
+
+        let bare_path = temp_path("base64_bare.txt");
+        write_file(bare_path.to_str().unwrap(), data_uri(&bytes, false).as_bytes());
+        let read_options = ReadOptions {
+            base64: true,
+            ..ReadOptions::default()
+        };
+        let from_bare = read_png_from_file(bare_path.to_str().unwrap(), &read_options);
+
+        fs::remove_file(&uri_path).unwrap();
+        fs::remove_file(&bare_path).unwrap();
+
+        assert_eq!(from_uri.as_bytes(), bytes);
+        assert_eq!(from_bare.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode_base64(b"data:image/png;base64,aGk=").unwrap(), b"hi");
+        assert_eq!(decode_base64(b"aG\nk=\n").unwrap(), b"hi");
+        assert!(decode_base64(b"not base64!").is_err());
+    }
+
+    #[test]
+    fn test_read_png_from_memory() {
+        let reader = std::io::Cursor::new(testing_png().as_bytes());
+        let png = read_png(reader, "memory", &ReadOptions::default()).ok().unwrap();
+
+        assert_eq!(decode_message(&png, "ruSt", false).ok(), Some("I am the secret".to_string()));
+
+        let options = ReadOptions {
+            base64: true,
+            ..ReadOptions::default()
+        };
+        let report = read_png(&b"not base64!"[..], "memory", &options).err().unwrap();
+
+        assert_eq!(report.kind, "decode");
+        assert_eq!(report.message, "Error decoding base64 file `memory`: invalid base64");
+    }
+
+    #[test]
+    fn test_fix_bits() {
+        let path = temp_path("fix_bits.png");
+        let filename = path.to_str().unwrap();
+
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("RUST").unwrap(), b"bad bits".to_vec()));
+        write_file(filename, &png.as_bytes());
+
+        fix_bits(filename, "RUST", true, true, true, &ReadOptions::default());
+
+        let png = read_png_from_file(filename, &ReadOptions::default());
+        fs::remove_file(&path).unwrap();
+
+        let chunk = png.chunk_by_type("ruSt").unwrap();
+        assert!(chunk.chunk_type().is_valid());
+        assert!(png.chunk_by_type("RUST").is_none());
+    }
+
+    #[test]
+    fn test_fix_chunk_type() {
+        let chunk_type = ChunkType::from_str("Rust").unwrap();
+
+        let fixed = fix_chunk_type(&chunk_type, false, false, false);
+        assert_eq!(fixed.as_str(), "RuSt");
+        assert!(fixed.is_valid());
+
+        assert_eq!(fix_chunk_type(&chunk_type, true, false, false).as_str(), "ruSt");
+        assert_eq!(fix_chunk_type(&ChunkType::from_str("RUST").unwrap(), false, true, true).as_str(), "RuSt");
+    }
+
+    #[test]
+    fn test_find_matches() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("biNa").unwrap(), vec![0, 0xca, 0xfe, 0, 0xca, 0xfe]));
+
+        let matches = find_matches(&png, b"secret");
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].index, &matches[0].chunk_type[..]), (1, "ruSt"));
+        assert_eq!(matches[0].offsets, vec![9]);
+
+        let matches = find_matches(&png, &from_hex("ca fe").unwrap());
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].index, &matches[0].chunk_type[..]), (2, "biNa"));
+        assert_eq!(matches[0].offsets, vec![1, 4]);
+
+        assert!(find_matches(&png, b"missing").is_empty());
+    }
+
+    #[test]
+    fn test_image_data_size_warning() {
+        let ihdr = Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        let png = Png::from_chunks(vec![ihdr]);
+
+        assert_eq!(image_data_size_warning(&png, 14), None);
+        assert_eq!(
+            image_data_size_warning(&png, 10),
+            Some("Warning: image data decompresses to 10 bytes, but IHDR describes 14 bytes".to_string())
+        );
+    }
+
+    #[test]
+    fn test_count_only_exit_code() {
+        assert_eq!(count_only_exit_code(0), 1);
+        assert_eq!(count_only_exit_code(1), 0);
+        assert_eq!(count_only_exit_code(12), 0);
+    }
+
+    #[test]
+    fn test_from_hex() {
+        assert_eq!(from_hex("00ff 7F").unwrap(), vec![0, 255, 127]);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn test_chunk_size_warning() {
+        assert!(chunk_size_warning(DEFAULT_MAX_CHUNK_SIZE + 1, DEFAULT_MAX_CHUNK_SIZE).is_some());
+        assert!(chunk_size_warning(DEFAULT_MAX_CHUNK_SIZE, DEFAULT_MAX_CHUNK_SIZE).is_none());
+        assert!(chunk_size_warning(5, DEFAULT_MAX_CHUNK_SIZE).is_none());
+        assert!(chunk_size_warning(5, 4).unwrap().contains("5 bytes"));
+    }
+
+    #[test]
+    fn test_encoded_chunk_len_after_concat() {
+        let mut png = testing_png();
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        concat_or_add_chunk(&mut png, Chunk::new(chunk_type, b" too".to_vec())).unwrap();
+
+        // "I am the secret" plus " too"
+        assert_eq!(encoded_chunk_len(&png, &chunk_type, true, 4), 19);
+        assert_eq!(encoded_chunk_len(&png, &chunk_type, false, 4), 4);
+        assert_eq!(encoded_chunk_len(&png, &ChunkType::from_str("neWw").unwrap(), true, 4), 4);
+    }
+
+    #[test]
+    fn test_chunk_not_found_message() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"again".to_vec()));
+        png.append_chunk(Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"Comment\0hi".to_vec()));
+
+        assert_eq!(
+            chunk_not_found_message(&png, "RuSt"),
+            "Chunk type `RuSt` not found, ancillary chunk types in the file: ruSt, tEXt"
+        );
+
+        let png = Png::from_chunks(vec![Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![])]);
+        assert_eq!(chunk_not_found_message(&png, "ruSt"), "Chunk type `ruSt` not found, the file has no ancillary chunks");
+    }
+
+    #[test]
+    fn test_render_order_violations() {
+        let chunk = |chunk_type: &str| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), vec![]);
+        let png = Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("pHYs"), chunk("IEND"), chunk("ruSt")]);
+
+        assert_eq!(render_order_violations(&png), vec![
+            "PNG",
+            "Chunk { type:IHDR, length:0 }",
+            "Chunk { type:IDAT, length:0 }",
+            "Chunk { type:pHYs, length:0 }  <- out of order: must come before IDAT",
+            "Chunk { type:IEND, length:0 }",
+            "Chunk { type:ruSt, length:0 }  <- out of order: comes after IEND",
+        ]);
+    }
+
+    #[test]
+    fn test_render_list() {
+        let png = testing_png();
+        let crc = png.chunks()[1].crc();
+
+        let csv = render_list(&png, OutputFormat::Csv, &TypeFilter::default());
+        assert_eq!(csv.len(), 3);
+        assert_eq!(csv[0], "index,type,length,crc,critical,public,safe_to_copy");
+        assert_eq!(csv[2], format!("1,ruSt,15,{:#010x},false,false,true", crc));
+
+        let text = render_list(&png, OutputFormat::Text, &TypeFilter::default());
+        assert_eq!(text[1], format!("1 ruSt length:15 crc:{:#010x}", crc));
+    }
+
+    #[test]
+    fn test_render_list_type_filter() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![0; 4]));
+        png.append_chunk(Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"a\0b".to_vec()));
+        let types = |filter: TypeFilter| -> Vec<String> {
+            render_list(&png, OutputFormat::Text, &filter)
+                .iter()
+                .map(|line| line.split(' ').take(2).collect::<Vec<_>>().join(" "))
+                .collect()
+        };
+        let strings = |types: &[&str]| types.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+
+        let only = TypeFilter { only: Some(strings(&["tEXt", "ruSt"])), ..TypeFilter::default() };
+        assert_eq!(types(only), vec!["1 ruSt", "3 tEXt"]);
+
+        let exclude = TypeFilter { exclude: strings(&["IDAT"]), ..TypeFilter::default() };
+        assert_eq!(types(exclude), vec!["0 FrSt", "1 ruSt", "3 tEXt"]);
+
+        let both = TypeFilter { only: Some(strings(&["tEXt", "IDAT"])), exclude: strings(&["IDAT"]) };
+        assert_eq!(types(both), vec!["3 tEXt"]);
+    }
+
+    #[test]
+    fn test_render_counts() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"again".to_vec()));
+
+        assert_eq!(render_counts(&png, OutputFormat::Csv), vec![
+            "type,count,total_bytes",
+            "FrSt,1,32",
+            "ruSt,2,44",
+        ]);
+        assert_eq!(render_counts(&png, OutputFormat::Text), vec![
+            "FrSt: 1 chunk(s), 32 bytes",
+            "ruSt: 2 chunk(s), 44 bytes",
+        ]);
+    }
+
+    #[test]
+    fn test_render_counts_sorted_by_type() {
+        let chunk = |chunk_type: &str| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), vec![]);
+        let png = Png::from_chunks(vec![chunk("zzZz"), chunk("IDAT"), chunk("aaAa"), chunk("IDAT"), chunk("IEND")]);
+
+        let expected = vec![
+            "IDAT: 2 chunk(s), 24 bytes",
+            "IEND: 1 chunk(s), 12 bytes",
+            "aaAa: 1 chunk(s), 12 bytes",
+            "zzZz: 1 chunk(s), 12 bytes",
+        ];
+
+        for _ in 0..3 {
+            assert_eq!(render_counts(&png, OutputFormat::Text), expected);
+        }
+    }
+
+    #[test]
+    fn test_remove_chunks_bounded() {
+        let several = || {
+            let mut png = testing_png();
+            for msg in ["second", "third", "fourth"] {
+                png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), msg.as_bytes().to_vec()));
+            }
+            png
+        };
+        let indices = |removed: Vec<(usize, Chunk)>| removed.into_iter().map(|(i, _)| i).collect::<Vec<_>>();
+
+        // --keep 1
+        let mut png = several();
+        assert_eq!(indices(remove_chunks_bounded(&mut png, "ruSt", 1, None)), vec![2, 3, 4]);
+        assert_eq!(decode_message(&png, "ruSt", false).ok().unwrap(), "I am the secret");
+
+        // --max-remove 2
+        let mut png = several();
+        assert_eq!(indices(remove_chunks_bounded(&mut png, "ruSt", 0, Some(2))), vec![1, 2]);
+        assert_eq!(decode_message(&png, "ruSt", false).ok().unwrap(), "third");
+
+        // both
+        let mut png = several();
+        assert_eq!(indices(remove_chunks_bounded(&mut png, "ruSt", 1, Some(1))), vec![2]);
+        assert_eq!(png.chunks().len(), 4);
+
+        let mut png = several();
+        assert!(remove_chunks_bounded(&mut png, "ruSt", 4, None).is_empty());
+    }
+
+    #[test]
+    fn test_render_removed_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"Another".to_vec()));
+
+        let removed = png.remove_all_chunks("ruSt");
+
+        assert_eq!(render_removed_chunks(&removed), vec![
+            "Removed chunk 1 { type:ruSt, length:15 }".to_string(),
+            "Removed chunk 2 { type:ruSt, length:7 }".to_string(),
+            "Removed 2 chunk(s)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_remove_chunk_at_guarded() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]));
+
+        assert!(remove_chunk_at_guarded(&mut png, 2, false).is_err());
+        assert!(remove_chunk_at_guarded(&mut png, 5, false).is_err());
+        assert_eq!(png.chunks().len(), 3);
+
+        let removed = remove_chunk_at_guarded(&mut png, 1, false).unwrap();
+        assert_eq!(&removed.chunk_type().to_string(), "ruSt");
+
+        let removed = remove_chunk_at_guarded(&mut png, 1, true).unwrap();
+        assert_eq!(&removed.chunk_type().to_string(), "IEND");
+    }
+
+    #[test]
+    fn test_render_ancillary_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("biNa").unwrap(), vec![0, 159, 146, 150]));
+
+        let lines = render_ancillary_chunks(&png);
+
+        assert_eq!(lines, vec![
+            "ruSt: `I am the secret`".to_string(),
+            "biNa (hex): 00 9f 92 96".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_encode_into_same_file() {
+        let path = temp_path("encode_in_place.png");
+        let filename = path.to_str().unwrap();
+        write_file(filename, &testing_png().as_bytes());
+
+        encode(filename, Some("teSt"), "Message", filename, &EncodeOptions::default(), &ReadOptions::default());
+
+        let png = read_png_from_file(filename, &ReadOptions::default());
+        let tmp_exists = fs::read_dir(path.parent().unwrap()).unwrap().any(|entry| {
+            let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+            name.starts_with("encode_in_place.png.") && name.ends_with(".pngme.tmp")
+        });
+        fs::remove_file(&path).unwrap();
+
+        assert!(!tmp_exists);
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(&png.chunk_by_type("teSt").unwrap().data_as_string().unwrap(), "Message");
+        assert_eq!(&png.chunk_by_type("ruSt").unwrap().data_as_string().unwrap(), "I am the secret");
+    }
+
+    #[test]
+    fn test_auto_chunk_type_is_deterministic() {
+        let candidates: Vec<String> = auto_chunk_type_candidates().iter().map(|c| c.to_string()).collect();
+        assert_eq!(candidates.len(), 26);
+        assert_eq!(&candidates[..3], &["msGe", "msAe", "msBe"]);
+        assert_eq!(candidates.last().unwrap(), "msZe");
+
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("msGe").unwrap(), vec![]));
+
+        for _ in 0..10 {
+            assert_eq!(unused_chunk_type(&png).unwrap().to_string(), "msAe");
+        }
+    }
+
+    #[test]
+    fn test_encode_message_from_env() {
+        let path = temp_path("encode_message_env.png");
+        let filename = path.to_str().unwrap();
+        write_file(filename, &testing_png().as_bytes());
+
+        std::env::set_var("PNGME_TEST_ENCODE_MSG", "secret from the environment");
+        let msg = message_from_env("PNGME_TEST_ENCODE_MSG");
+        encode(filename, Some("enVr"), &msg, filename, &EncodeOptions::default(), &ReadOptions::default());
+
+        let png = read_png_from_file(filename, &ReadOptions::default());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(&png.chunk_by_type("enVr").unwrap().data_as_string().unwrap(), "secret from the environment");
+    }
+
+    #[test]
+    fn test_encode_with_auto_type() {
+        let path = temp_path("encode_auto_type.png");
+        let filename = path.to_str().unwrap();
+        write_file(filename, &testing_png().as_bytes());
+
+        encode(filename, None, "first", filename, &EncodeOptions::default(), &ReadOptions::default());
+        encode(filename, None, "second", filename, &EncodeOptions::default(), &ReadOptions::default());
+
+        let png = read_png_from_file(filename, &ReadOptions::default());
+        fs::remove_file(&path).unwrap();
+
+        let first = png.chunk_by_type("msGe").unwrap();
+        assert!(first.chunk_type().is_valid());
+        assert!(!first.chunk_type().is_critical());
+        assert!(!first.chunk_type().is_public());
+        assert!(first.chunk_type().is_safe_to_copy());
+        assert_eq!(&first.data_as_string().unwrap(), "first");
+        assert_eq!(&png.chunk_by_type("msAe").unwrap().data_as_string().unwrap(), "second");
+    }
+
+    #[test]
+    fn test_preserve_mtime() {
+        let path = temp_path("preserve_mtime.png");
+        let filename = path.to_str().unwrap();
+        write_file(filename, &testing_png().as_bytes());
+
+        let mtime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        restore_mtime(filename, mtime);
+
+        set_preserve_mtime(true);
+        remove(filename, "ruSt", None, &ReadOptions::default());
+        encode(filename, Some("teSt"), "Message", filename, &EncodeOptions::default(), &ReadOptions::default());
+        set_preserve_mtime(false);
+
+        let modified = fs::metadata(filename).unwrap().modified().unwrap();
+        let png = read_png_from_file(filename, &ReadOptions::default());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(modified, mtime);
+        assert!(png.chunk_by_type("ruSt").is_none());
+        assert!(png.chunk_by_type("teSt").is_some());
+    }
+
+    #[test]
+    fn test_stdin_input_is_not_written_back() {
+        let report = check_output_filename("-").err().unwrap();
+        assert_eq!(report.kind, "invalid_input");
+        assert_eq!(report.message, "Can't write the result back to `-` (stdin), give an output file name");
+
+        let report = write_new_file("-", &testing_png().as_bytes()).err().unwrap();
+        assert_eq!(report.kind, "invalid_input");
+        assert!(!std::path::Path::new("-").exists());
+
+        assert!(check_output_filename(temp_path("stdin_output.png").to_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_derive_output_path() {
+        assert_eq!(derive_output_path("cat.png", Some(".stego"), None), "cat.stego.png");
+        assert_eq!(derive_output_path("images/cat.png", Some(".stego"), None), "images/cat.stego.png");
+        assert_eq!(derive_output_path("images/cat", Some("_out"), None), "images/cat_out.png");
+        assert_eq!(derive_output_path("images/cat.png", None, Some("out")), "out/cat.png");
+        assert_eq!(derive_output_path("images/cat.png", Some(".stego"), Some("/tmp/out")), "/tmp/out/cat.stego.png");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_file_atomic_follows_symlinks_and_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let target = temp_path("atomic_target.png");
+        let link = temp_path("atomic_link.png");
+        write_file(target.to_str().unwrap(), b"before");
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640)).unwrap();
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write_file_atomic(link.to_str().unwrap(), b"after");
+
+        let link_is_symlink = fs::symlink_metadata(&link).unwrap().file_type().is_symlink();
+        let contents = fs::read(&target).unwrap();
+        let mode = fs::metadata(&target).unwrap().permissions().mode() & 0o777;
+        fs::remove_file(&link).unwrap();
+        fs::remove_file(&target).unwrap();
+
+        assert!(link_is_symlink);
+        assert_eq!(contents, b"after");
+        assert_eq!(mode, 0o640);
+    }
+
+    #[test]
+    fn test_encode_no_overwrite() {
+        let input_path = temp_path("no_overwrite_input.png");
+        let output_path = temp_path("no_overwrite_output.png");
+        let (input, output) = (input_path.to_str().unwrap(), output_path.to_str().unwrap());
+        write_file(input, &testing_png().as_bytes());
+
+        let options = EncodeOptions {
+            no_overwrite: true,
+            ..EncodeOptions::default()
+        };
+        encode(input, Some("teSt"), "Message", output, &options, &ReadOptions::default());
+        let png = read_png_from_file(output, &ReadOptions::default());
+
+        let report = write_new_file(output, b"replaced").err().unwrap();
+        let unchanged = read_png_from_file(output, &ReadOptions::default());
+
+        fs::remove_file(&input_path).unwrap();
+        fs::remove_file(&output_path).unwrap();
+
+        assert!(png.contains_chunk_type("teSt"));
+        assert_eq!(report.kind, "exists");
+        assert_eq!(unchanged.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_render_embedded_png() {
+        let chunk = |chunk_type: &str, data: Vec<u8>| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data);
+        let inner = Png::from_chunks(vec![chunk("IHDR", vec![0; 13]), chunk("IEND", vec![])]);
+        let middle = Png::from_chunks(vec![chunk("ruSt", inner.as_bytes()), chunk("IEND", vec![])]);
+
+        assert_eq!(
+            render_embedded_png(&middle.as_bytes(), 1, DEFAULT_MAX_DEPTH),
+            vec![
+                "  0 ruSt length:45, holds a PNG:",
+                "    0 IHDR length:13",
+                "    1 IEND length:0",
+                "  1 IEND length:0",
+            ]
+        );
+        assert_eq!(
+            render_embedded_png(&middle.as_bytes(), 1, 1),
+            vec![
+                "  0 ruSt length:45, holds a PNG:",
+                "    (not shown, deeper than --max-depth 1)",
+                "  1 IEND length:0",
+            ]
+        );
+
+        let mut broken = inner.as_bytes();
+        broken.truncate(20);
+        let lines = render_embedded_png(&broken, 1, DEFAULT_MAX_DEPTH);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("  (can't be read: "));
+    }
+
+    #[test]
+    fn test_run_selftest() {
+        let dir = temp_path("selftest_dir");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+
+        let passed = run_selftest(&dir);
+        let leftovers = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir(&dir).unwrap();
+
+        assert_eq!(passed, Ok(()));
+        assert_eq!(leftovers, 0);
+        assert!(run_selftest(&temp_path("missing_dir")).is_err());
+    }
+
+    #[test]
+    fn test_text_by_keyword() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::text("Title", "A picture").unwrap());
+        png.append_chunk(Chunk::text("Author", "Paul").unwrap());
+        png.append_chunk(Chunk::new(ChunkType::from_str("iTXt").unwrap(), b"Comment\0\0\0\0\0Nice".to_vec()));
+        png.append_chunk(Chunk::text("Author", "Someone else").unwrap());
+
+        assert_eq!(text_by_keyword(&png, "Author").ok(), Some("Paul".to_string()));
+        assert_eq!(text_by_keyword(&png, "Comment").ok(), Some("Nice".to_string()));
+
+        let report = text_by_keyword(&png, "author").err().unwrap();
+        assert_eq!(report.kind, "not_found");
+        assert_eq!(
+            report.message,
+            "No text chunk with keyword `author`, keywords in the file: Title, Author, Comment, Author"
+        );
+        assert_eq!(
+            text_by_keyword(&testing_png(), "Author").err().unwrap().message,
+            "No text chunk with keyword `Author`, the file has no text chunks"
+        );
+    }
+
+    #[test]
+    fn test_chunk_json() {
+        let png = testing_png();
+        let json = |chunk: &Chunk| serde_json::from_str::<serde_json::Value>(&chunk_json(chunk)).unwrap();
+
+        let chunk = png.chunk_by_type("ruSt").unwrap();
+        assert_eq!(
+            json(chunk),
+            serde_json::json!({
+                "chunk_type": "ruSt",
+                "length": 15,
+                "crc": format!("{:#010x}", chunk.crc()),
+                "data_utf8": "I am the secret",
+                "data_base64": "SSBhbSB0aGUgc2VjcmV0",
+            })
+        );
+
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![0xff, 0x00]);
+        assert_eq!(
+            json(&chunk),
+            serde_json::json!({
+                "chunk_type": "ruSt",
+                "length": 2,
+                "crc": format!("{:#010x}", chunk.crc()),
+                "data_utf8": null,
+                "data_base64": "/wA=",
+            })
+        );
+    }
+
+    #[test]
+    fn test_limit_rows() {
+        let lines = || ["PNG", "0", "1", "2", "3", "4"].iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(limit_rows(lines(), 1, RowLimit::All, true), lines());
+        assert_eq!(limit_rows(lines(), 1, RowLimit::First(2), true), vec!["PNG", "0", "1", "... (3 more)"]);
+        assert_eq!(limit_rows(lines(), 1, RowLimit::Last(2), true), vec!["PNG", "... (3 more)", "3", "4"]);
+        assert_eq!(limit_rows(lines(), 1, RowLimit::First(2), false), vec!["PNG", "0", "1"]);
+        // nothing hidden, no note
+        assert_eq!(limit_rows(lines(), 1, RowLimit::Last(5), true), lines());
+        assert_eq!(limit_rows(lines(), 0, RowLimit::First(1), true), vec!["PNG", "... (5 more)"]);
+        assert_eq!(limit_rows(vec![], 1, RowLimit::First(1), true), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_limit_rows_on_print_output() {
+        let mut png = testing_png();
+        for _ in 0..5 {
+            png.append_chunk(Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![0; 4]));
+        }
+        let lines: Vec<String> = png.describe().to_string().lines().map(String::from).collect();
+
+        assert_eq!(
+            limit_rows(lines, 1, RowLimit::First(3), true),
+            vec![
+                "PNG",
+                "Chunk { type:FrSt, length:20 }",
+                "Chunk { type:ruSt, length:15 }",
+                "Chunk { type:IDAT, length:4 }",
+                "... (4 more)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_grouped() {
+        let chunk = |chunk_type: &str, len: usize| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), vec![0; len]);
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", 13),
+            chunk("IDAT", 100),
+            chunk("IDAT", 100),
+            chunk("IDAT", 50),
+            chunk("tEXt", 4),
+            chunk("IDAT", 8),
+            chunk("IEND", 0),
+        ]);
+
+        assert_eq!(
+            render_grouped(&png),
+            vec![
+                "PNG",
+                "Chunk { type:IHDR, length:13 }",
+                "IDAT \u{d7} 3 (total 250 bytes)",
+                "Chunk { type:tEXt, length:4 }",
+                "Chunk { type:IDAT, length:8 }",
+                "Chunk { type:IEND, length:0 }",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_chunk_before_iend() {
+        let chunk = |chunk_type: &str| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), vec![]);
+
+        let mut png = Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")]);
+        assert_eq!(add_chunk_before_iend(&mut png, chunk("ruSt")), Ok(2));
+        assert!(add_chunk_before_iend(&mut png, chunk("IEND")).is_err());
+        assert_eq!(png.validate_structure(), Ok(()));
+
+        let mut png = testing_png();
+        assert_eq!(add_chunk_before_iend(&mut png, chunk("teSt")), Ok(2));
+    }
+
+    #[test]
+    fn test_checked_png_bytes() {
+        let chunk = |chunk_type: &str| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), vec![]);
+        let png = Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")]);
+
+        assert_eq!(checked_png_bytes(&png, true, CrcPolicy::All), png.as_bytes());
+        // already malformed input is written back unchecked
+        assert_eq!(checked_png_bytes(&testing_png(), false, CrcPolicy::All), testing_png().as_bytes());
+    }
+
+    #[test]
+    fn test_verify_round_trip() {
+        let png = testing_png();
+        assert_eq!(verify_round_trip(&png, &png.as_bytes(), CrcPolicy::All), Ok(()));
+
+        // edited without `sync_length`, so the written length is wrong
+        let mut stale_length = testing_png();
+        stale_length.chunk_by_type_mut("ruSt").unwrap().data_mut().extend_from_slice(b" and more");
+        assert!(verify_round_trip(&stale_length, &stale_length.as_bytes(), CrcPolicy::All).is_err());
+
+        let mut stale_crc = testing_png();
+        let chunk = stale_crc.chunk_by_type_mut("ruSt").unwrap();
+        chunk.data_mut().extend_from_slice(b" and more");
+        chunk.sync_length();
+        assert_eq!(
+            verify_round_trip(&stale_crc, &stale_crc.as_bytes(), CrcPolicy::All),
+            Err("error reading chunk")
+        );
+        // a lenient policy accepts it as it was read
+        assert_eq!(verify_round_trip(&stale_crc, &stale_crc.as_bytes(), CrcPolicy::None), Ok(()));
+    }
+
+    #[test]
+    fn test_pad_data() {
+        assert_eq!(pad_data(b"abc".to_vec(), 5), Ok(b"abc\0\0".to_vec()));
+        assert_eq!(pad_data(b"abc".to_vec(), 3), Ok(b"abc".to_vec()));
+        assert!(pad_data(b"abc".to_vec(), 2).is_err());
+    }
+
+    #[test]
+    fn test_encode_safe_to_copy() {
+        let path = temp_path("safe_to_copy.png");
+        let filename = path.to_str().unwrap();
+        write_file(filename, &testing_png().as_bytes());
+
+        let options = EncodeOptions {
+            safe_to_copy: true,
+            ..EncodeOptions::default()
+        };
+        encode(filename, Some("teST"), "Message", filename, &options, &ReadOptions::default());
+        let png = read_png_from_file(filename, &ReadOptions::default());
+
+        fs::remove_file(&path).unwrap();
+
+        let chunk = png.chunk_by_type("teSt").unwrap();
+        assert!(chunk.chunk_type().is_safe_to_copy());
+        assert!(png.chunk_by_type("teST").is_none());
+    }
+
+    #[test]
+    fn test_encode_max_message_size() {
+        let path = temp_path("max_message_size.png");
+        let filename = path.to_str().unwrap();
+        write_file(filename, &testing_png().as_bytes());
+
+        let options = EncodeOptions {
+            max_message_size: Some(5),
+            ..EncodeOptions::default()
+        };
+        encode(filename, Some("teSt"), "short", filename, &options, &ReadOptions::default());
+        let png = read_png_from_file(filename, &ReadOptions::default());
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(decode_message(&png, "teSt", false).ok(), Some("short".to_string()));
+    }
+
+    #[test]
+    fn test_encode_pad_to() {
+        let path = temp_path("pad_to.png");
+        let filename = path.to_str().unwrap();
+        write_file(filename, &testing_png().as_bytes());
+
+        let options = EncodeOptions {
+            pad_to: Some(16),
+            ..EncodeOptions::default()
+        };
+        encode(filename, Some("reCd"), "short", filename, &options, &ReadOptions::default());
+        let png = read_png_from_file(filename, &ReadOptions::default());
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(png.chunk_by_type("reCd").unwrap().length(), 16);
+        let msg = decode_message(&png, "reCd", false).ok().unwrap();
+        assert_eq!(msg.trim_end_matches('\0'), "short");
+    }
+
+    #[test]
+    fn test_encode_concat() {
+        let path = temp_path("concat.png");
+        let filename = path.to_str().unwrap();
+        write_file(filename, &testing_png().as_bytes());
+
+        let options = EncodeOptions {
+            concat: true,
+            ..EncodeOptions::default()
+        };
+        encode(filename, Some("ruSt"), ", twice", filename, &options, &ReadOptions::default());
+        encode(filename, Some("ruSt"), " and three times", filename, &options, &ReadOptions::default());
+        encode(filename, Some("loGs"), "new", filename, &options, &ReadOptions::default());
+        let png = read_png_from_file(filename, &ReadOptions::default());
+
+        fs::remove_file(&path).unwrap();
+
+        let types: Vec<String> = png.chunk_types().map(|c| c.to_string()).collect();
+        assert_eq!(types, vec!["FrSt", "ruSt", "loGs"]);
+        assert_eq!(
+            decode_message(&png, "ruSt", false).ok(),
+            Some("I am the secret, twice and three times".to_string())
+        );
+        assert!(png.chunk_by_type("ruSt").unwrap().has_valid_crc());
+        assert_eq!(decode_message(&png, "loGs", false).ok(), Some("new".to_string()));
+    }
+
+    #[test]
+    fn test_encode_upsert() {
+        let path = temp_path("upsert.png");
+        let filename = path.to_str().unwrap();
+
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"I am another secret".to_vec()));
+        write_file(filename, &png.as_bytes());
+
+        let options = EncodeOptions {
+            upsert: true,
+            ..EncodeOptions::default()
+        };
+        encode(filename, Some("ruSt"), "New secret", filename, &options, &ReadOptions::default());
+        let png = read_png_from_file(filename, &ReadOptions::default());
+
+        fs::remove_file(&path).unwrap();
+
+        let types: Vec<String> = png.chunk_types().map(|c| c.to_string()).collect();
+        assert_eq!(types, vec!["FrSt", "ruSt"]);
+        assert_eq!(decode_message(&png, "ruSt", false).ok(), Some("New secret".to_string()));
+    }
+
+    #[test]
+    fn test_encode_framed_round_trip() {
+        let path = temp_path("framed.png");
+        let filename = path.to_str().unwrap();
+        write_file(filename, &testing_png().as_bytes());
+
+        let options = EncodeOptions {
+            framed: true,
+            ..EncodeOptions::default()
+        };
+        encode(filename, Some("teSt"), "Message\0\0", filename, &options, &ReadOptions::default());
+        let png = read_png_from_file(filename, &ReadOptions::default());
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(&png.chunk_by_type("teSt").unwrap().data()[..8], b"PME1\0\0\0\x09");
+        assert_eq!(decode_message(&png, "teSt", true).ok().unwrap(), "Message\0\0");
+    }
+
+    #[test]
+    fn test_decode_framed_rejects_unframed_data() {
+        let report = decode_message(&testing_png(), "ruSt", true).err().unwrap();
+
+        assert_eq!(report.kind, "invalid_data");
+        assert_eq!(
+            report.message,
+            "Error reading framed message: missing PME1 header, the chunk was not written with --framed"
+        );
+    }
+
+    #[test]
+    fn test_disallowed_text_byte() {
+        assert_eq!(disallowed_text_byte(b"first line\nsecond line"), None);
+        assert_eq!(disallowed_text_byte(b"bell\x07"), Some((4, 0x07)));
+        assert_eq!(disallowed_text_byte(b"tab\there"), Some((3, b'\t')));
+        assert_eq!(disallowed_text_byte("caf\u{e9}".as_bytes()), Some((3, 0xc3)));
+    }
+
+    #[test]
+    fn test_unframe_message() {
+        let mut framed = frame_message(b"abc");
+        framed.extend_from_slice(b"padding");
+        assert_eq!(unframe_message(&framed), Ok(&b"abc"[..]));
+
+        let mut truncated = frame_message(b"abc");
+        truncated.pop();
+        assert_eq!(unframe_message(&truncated), Err("framed length is longer than the chunk data"));
+    }
+
+    #[test]
+    fn test_is_same_file() {
+        let path = temp_path("same_file.png");
+        let filename = path.to_str().unwrap();
+        write_file(filename, &[]);
+
+        let dir = path.parent().unwrap();
+        let roundabout = dir.join(".").join(path.file_name().unwrap());
+
+        let same = is_same_file(filename, roundabout.to_str().unwrap());
+        let missing = is_same_file(filename, temp_path("missing.png").to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert!(same);
+        assert!(!missing);
+    }
+
+    #[test]
+    fn test_read_gzipped_png() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        let png = testing_png();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&png.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let path = temp_path("gzipped.png.gz");
+        write_file(path.to_str().unwrap(), &compressed);
+
+        let actual = read_png_from_file(path.to_str().unwrap(), &ReadOptions::default());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(actual.as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_uncompressed_input_passes_through() {
+        let bytes = testing_png().as_bytes();
+        assert_eq!(decompress_if_gzip(bytes.clone(), 1).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_gzip_inflate_limit() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0; 1000]).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(decompress_if_gzip(compressed.clone(), 1000).unwrap(), vec![0; 1000]);
+        assert_eq!(decompress_if_gzip(compressed, 999).err().unwrap(), "inflates to more than 999 bytes");
+    }
+
+    #[test]
+    fn test_crc_error_report_json() {
+        let png = testing_png();
+        let expected = png.chunks()[1].crc();
+
+        let mut bytes = png.as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        let actual = expected ^ 0xff;
+
+        let err = Png::try_from_with_policy(&bytes[..], CrcPolicy::All).err().unwrap();
+        let report = parse_error_report(&bytes, false, CrcPolicy::All, err);
+
+        assert_eq!(report.to_json(), format!(
+            "{{\"kind\":\"crc\",\"message\":\"Error parsing PNG \\\"{}\\\" at offset 40\",\"chunk_type\":\"ruSt\",\"offset\":40,\"expected_crc\":\"{:#010x}\",\"actual_crc\":\"{:#010x}\"}}",
+            err, expected, actual
+        ));
+    }
+
+    #[test]
+    fn test_parse_error_report_without_crc_context() {
+        let bytes = b"not a png".to_vec();
+
+        let err = Png::try_from_with_policy(&bytes[..], CrcPolicy::All).err().unwrap();
+        let report = parse_error_report(&bytes, false, CrcPolicy::All, err);
+
+        assert_eq!(report.kind, "parse");
+        assert_eq!(report.message, "Error parsing PNG \"invalid png header\"");
+        assert!(report.context.is_empty());
+    }
+
+    #[test]
+    fn test_parse_error_report_after_valid_signature() {
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.extend_from_slice(b"\0\0\0\x01??\0\0 not a chunk");
+
+        let err = Png::try_from_with_policy(&bytes[..], CrcPolicy::All).err().unwrap();
+        let report = parse_error_report(&bytes, false, CrcPolicy::All, err);
+
+        assert_eq!(report.kind, "parse");
+        assert_eq!(report.message, "Error parsing PNG \"error reading chunk\" at offset 8");
+    }
+
+    #[test]
+    fn test_parse_error_report_hints_at_altered_reserved_bit() {
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        let mut chunk = testing_png().chunk_by_type("ruSt").unwrap().as_bytes();
+        // `ruSt` becomes `rust` with the crc left as it was
+        chunk[6] |= 0x20;
+        bytes.extend_from_slice(&chunk);
+
+        let err = Png::try_from_with_policy(&bytes[..], CrcPolicy::All).err().unwrap();
+        let report = parse_error_report(&bytes, false, CrcPolicy::All, err);
+
+        assert_eq!(report.kind, "crc");
+        assert!(report.message.ends_with(&format!("({})", RESERVED_BIT_CRC_HINT)));
+    }
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(json_string("a \"b\" \\ c\n\u{1}"), "\"a \\\"b\\\" \\\\ c\\n\\u0001\"");
+    }
+
+    // Answers a single request with `response`, waiting `delay` before writing it
+    #[cfg(feature = "network")]
+    fn mock_server(response: Vec<u8>, delay: Duration) -> String {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/image.png", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+
+            std::thread::sleep(delay);
+            let _ = stream.write_all(&response);
+        });
+
+        url
+    }
+
+    #[cfg(feature = "network")]
+    fn http_response(body: &[u8], content_length: bool) -> Vec<u8> {
+        let mut response = b"HTTP/1.1 200 OK\r\nConnection: close\r\n".to_vec();
+
+        if content_length {
+            response.extend(format!("Content-Length: {}\r\n", body.len()).as_bytes());
+        }
+
+        response.extend_from_slice(b"\r\n");
+        response.extend_from_slice(body);
+        response
+    }
+
+    #[test]
+    #[cfg(feature = "network")]
+    fn test_fetch_url() {
+        let bytes = testing_png().as_bytes();
+        let url = mock_server(http_response(&bytes, true), Duration::from_millis(0));
+
+        assert_eq!(fetch_url(&url, &ReadOptions::default()).unwrap(), bytes);
+    }
+
+    #[test]
+    #[cfg(feature = "network")]
+    fn test_fetch_url_too_large() {
+        let read_options = ReadOptions {
+            max_download: 16,
+            ..ReadOptions::default()
+        };
+
+        let url = mock_server(http_response(&[0; 17], true), Duration::from_millis(0));
+        assert!(fetch_url(&url, &read_options).unwrap_err().contains("larger than 16 bytes"));
+
+        // without a Content-Length the body is cut off while reading
+        let url = mock_server(http_response(&[0; 17], false), Duration::from_millis(0));
+        assert!(fetch_url(&url, &read_options).unwrap_err().contains("larger than 16 bytes"));
+    }
+
+    #[test]
+    #[cfg(feature = "network")]
+    fn test_fetch_url_too_slow() {
+        let read_options = ReadOptions {
+            download_timeout: Duration::from_millis(100),
+            ..ReadOptions::default()
+        };
+
+        let bytes = testing_png().as_bytes();
+        let url = mock_server(http_response(&bytes, true), Duration::from_secs(2));
+
+        assert!(fetch_url(&url, &read_options).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "watch")]
+    fn test_watch_file() {
+        use std::sync::mpsc;
+
+        let path = temp_path("watch.png");
+        let filename = path.to_str().unwrap().to_string();
+        write_file(&filename, &testing_png().as_bytes());
+
+        let (tx, rx) = mpsc::channel();
+        let watched = filename.clone();
+        std::thread::spawn(move || {
+            watch_file(&watched, Duration::from_millis(50), || {
+                let png = load_png(&watched, &ReadOptions::default()).ok();
+                let msg = png.and_then(|png| decode_message(&png, "teSt", false).ok());
+                tx.send(msg).is_ok()
+            })
+        });
+
+        // the watcher starts in the background, so keep touching the file until it reacts
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("teSt").unwrap(), b"changed".to_vec()));
+        let tmp_filename = format!("{}.tmp", filename);
+
+        let mut decoded = None;
+        for _ in 0..50 {
+            // replaced with a rename, like write_file_atomic does
+            write_file(&tmp_filename, &png.as_bytes());
+            fs::rename(&tmp_filename, &filename).unwrap();
+
+            if let Ok(msg) = rx.recv_timeout(Duration::from_millis(200)) {
+                decoded = msg;
+                break;
+            }
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(decoded, Some("changed".to_string()));
+    }
+}
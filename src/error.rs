@@ -0,0 +1,3 @@
+// Every error in the library is a static description of what went wrong
+pub type Error = &'static str;
+pub type Result<T> = std::result::Result<T, Error>;
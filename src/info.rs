@@ -0,0 +1,469 @@
+use crate::chunk::Chunk;
+use crate::png::Png;
+use crate::Result;
+
+// Image properties from `IHDR` and `PLTE` that other chunks are interpreted against
+struct Context {
+    bit_depth: Option<u8>,
+    color_type: Option<u8>,
+    palette_entries: Option<usize>,
+    // APNG frame chunks, reported along with `acTL`
+    fctl_chunks: usize,
+    fdat_chunks: usize,
+}
+
+impl Context {
+    fn from_png(png: &Png) -> Context {
+        Context {
+            bit_depth: png
+                .chunk_by_type("IHDR")
+                .and_then(|chunk| chunk.data().get(8).copied()),
+            color_type: png
+                .chunk_by_type("IHDR")
+                .and_then(|chunk| chunk.data().get(9).copied()),
+            palette_entries: png
+                .chunk_by_type("PLTE")
+                .map(|chunk| chunk.data().len() / 3),
+            fctl_chunks: png.chunk_count_by_type("fcTL"),
+            fdat_chunks: png.chunk_count_by_type("fdAT"),
+        }
+    }
+}
+
+// One line for every chunk `info` knows how to interpret, in file order
+pub fn describe(png: &Png) -> Vec<String> {
+    let context = Context::from_png(png);
+
+    png.chunks()
+        .iter()
+        .filter_map(|chunk| {
+            let chunk_type = chunk.chunk_type().as_str();
+
+            describe_chunk(chunk, &context).map(|description| match description {
+                Ok(s) => format!("{}: {}", chunk_type, s),
+                Err(err) => format!("{}: invalid ({})", chunk_type, err),
+            })
+        })
+        .collect()
+}
+
+fn describe_chunk(chunk: &Chunk, context: &Context) -> Option<Result<String>> {
+    let data = chunk.data();
+
+    match &*chunk.chunk_type().as_str() {
+        "sRGB" => Some(describe_srgb(data)),
+        "cHRM" => Some(describe_chrm(data)),
+        "tRNS" => Some(describe_trns(data, context)),
+        "sBIT" => Some(describe_sbit(data, context)),
+        "hIST" => Some(describe_hist(data, context)),
+        "acTL" => Some(describe_actl(data, context)),
+        "oFFs" => Some(describe_offs(data)),
+        "sCAL" => Some(describe_scal(data)),
+        _ => None,
+    }
+}
+
+fn describe_srgb(data: &[u8]) -> Result<String> {
+    if data.len() != 1 {
+        return Err("sRGB must be 1 byte");
+    }
+
+    let intent = match data[0] {
+        0 => "perceptual",
+        1 => "relative colorimetric",
+        2 => "saturation",
+        3 => "absolute colorimetric",
+        _ => return Err("unknown rendering intent"),
+    };
+
+    Ok(format!("rendering intent {}", intent))
+}
+
+fn describe_chrm(data: &[u8]) -> Result<String> {
+    if data.len() != 32 {
+        return Err("cHRM must be 32 bytes");
+    }
+
+    // stored as the value times 100000
+    let values: Vec<f64> = data
+        .chunks(4)
+        .map(|b| read_u32(b) as f64 / 100000.0)
+        .collect();
+
+    Ok(format!(
+        "white ({}, {}), red ({}, {}), green ({}, {}), blue ({}, {})",
+        values[0], values[1], values[2], values[3], values[4], values[5], values[6], values[7]
+    ))
+}
+
+fn describe_trns(data: &[u8], context: &Context) -> Result<String> {
+    match context.color_type {
+        // grayscale
+        Some(0) => {
+            if data.len() != 2 {
+                return Err("tRNS must be 2 bytes for grayscale images");
+            }
+
+            Ok(format!("gray key {}", read_u16(data)))
+        }
+        // truecolor
+        Some(2) => {
+            if data.len() != 6 {
+                return Err("tRNS must be 6 bytes for truecolor images");
+            }
+
+            Ok(format!(
+                "rgb key ({}, {}, {})",
+                read_u16(&data[0..2]),
+                read_u16(&data[2..4]),
+                read_u16(&data[4..6])
+            ))
+        }
+        // indexed
+        Some(3) => {
+            if data.len() > context.palette_entries.unwrap_or(256) {
+                return Err("tRNS has more entries than the palette");
+            }
+
+            Ok(format!("palette alpha {:?}", data))
+        }
+        Some(_) => Err("tRNS is not allowed for images with an alpha channel"),
+        None => Err("missing IHDR color type"),
+    }
+}
+
+fn describe_sbit(data: &[u8], context: &Context) -> Result<String> {
+    let (channels, expected_len): (&[&str], _) = match context.color_type {
+        Some(0) => (&["gray"], 1),
+        Some(2) | Some(3) => (&["red", "green", "blue"], 3),
+        Some(4) => (&["gray", "alpha"], 2),
+        Some(6) => (&["red", "green", "blue", "alpha"], 4),
+        Some(_) => return Err("unknown color type"),
+        None => return Err("missing IHDR color type"),
+    };
+
+    if data.len() != expected_len {
+        return Err("sBIT length doesn't match the color type");
+    }
+
+    // palette entries are always 8 bits per channel
+    let max_bits = match context.color_type {
+        Some(3) => 8,
+        _ => context.bit_depth.unwrap_or(16),
+    };
+
+    if data.iter().any(|&bits| bits == 0 || bits > max_bits) {
+        return Err("significant bits must be between 1 and the sample depth");
+    }
+
+    let channels: Vec<String> = channels
+        .iter()
+        .zip(data)
+        .map(|(channel, bits)| format!("{} {}", channel, bits))
+        .collect();
+
+    Ok(format!("significant bits {}", channels.join(", ")))
+}
+
+fn describe_hist(data: &[u8], context: &Context) -> Result<String> {
+    let palette_entries = match context.palette_entries {
+        Some(entries) => entries,
+        None => return Err("hIST requires a PLTE chunk"),
+    };
+
+    if data.len() != palette_entries * 2 {
+        return Err("hIST must have one entry per palette entry");
+    }
+
+    let frequencies: Vec<u16> = data.chunks(2).map(read_u16).collect();
+
+    Ok(format!("palette histogram {:?}", frequencies))
+}
+
+// Only animated PNGs have an `acTL` chunk
+fn describe_actl(data: &[u8], context: &Context) -> Result<String> {
+    if data.len() != 8 {
+        return Err("acTL must be 8 bytes");
+    }
+
+    let (num_frames, num_plays) = (read_u32(&data[0..4]), read_u32(&data[4..8]));
+
+    if num_frames == 0 {
+        return Err("acTL must have at least one frame");
+    }
+
+    let plays = match num_plays {
+        0 => "infinite".to_string(),
+        n => n.to_string(),
+    };
+
+    Ok(format!(
+        "animated, {} frame(s), plays {}, {} fcTL and {} fdAT chunk(s)",
+        num_frames, plays, context.fctl_chunks, context.fdat_chunks
+    ))
+}
+
+// Where the image sits on a printed page, from the extension chunks
+fn describe_offs(data: &[u8]) -> Result<String> {
+    if data.len() != 9 {
+        return Err("oFFs must be 9 bytes");
+    }
+
+    let unit = match data[8] {
+        0 => "pixels",
+        1 => "micrometres",
+        _ => return Err("unknown oFFs unit"),
+    };
+
+    Ok(format!("offset ({}, {}) {}", read_i32(&data[0..4]), read_i32(&data[4..8]), unit))
+}
+
+// The physical size of a pixel, as two ASCII floats separated by a null byte
+fn describe_scal(data: &[u8]) -> Result<String> {
+    let unit = match data.first() {
+        Some(1) => "metres",
+        Some(2) => "radians",
+        Some(_) => return Err("unknown sCAL unit"),
+        None => return Err("sCAL must have a unit"),
+    };
+
+    let mut values = data[1..].splitn(2, |b| *b == 0);
+    let (width, height) = match (values.next(), values.next()) {
+        (Some(width), Some(height)) => (read_ascii_float(width)?, read_ascii_float(height)?),
+        _ => return Err("sCAL must have a width and a height"),
+    };
+
+    if width <= 0.0 || height <= 0.0 {
+        return Err("sCAL width and height must be greater than zero");
+    }
+
+    Ok(format!("pixel size {} x {} {}", width, height, unit))
+}
+
+// Digits with an optional point and exponent, unlike `f64::from_str` this refuses e.g. `inf`
+fn read_ascii_float(b: &[u8]) -> Result<f64> {
+    if !b.iter().all(|b| b.is_ascii_digit() || b"+-.eE".contains(b)) {
+        return Err("sCAL value is not a floating-point number");
+    }
+
+    std::str::from_utf8(b)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or("sCAL value is not a floating-point number")
+}
+
+fn read_i32(b: &[u8]) -> i32 {
+    i32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn read_u16(b: &[u8]) -> u16 {
+    u16::from_be_bytes([b[0], b[1]])
+}
+
+fn read_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    #[test]
+    fn test_describe_srgb() {
+        let png = Png::from_chunks(vec![chunk("sRGB", vec![1])]);
+        assert_eq!(describe(&png), vec!["sRGB: rendering intent relative colorimetric"]);
+    }
+
+    #[test]
+    fn test_describe_invalid_srgb() {
+        let png = Png::from_chunks(vec![chunk("sRGB", vec![0, 0]), chunk("sRGB", vec![4])]);
+        assert_eq!(describe(&png), vec![
+            "sRGB: invalid (sRGB must be 1 byte)",
+            "sRGB: invalid (unknown rendering intent)",
+        ]);
+    }
+
+    #[test]
+    fn test_describe_chrm() {
+        let values: [u32; 8] = [31270, 32900, 64000, 33000, 30000, 60000, 15000, 6000];
+        let data = values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect();
+
+        let png = Png::from_chunks(vec![chunk("cHRM", data)]);
+        assert_eq!(describe(&png), vec![
+            "cHRM: white (0.3127, 0.329), red (0.64, 0.33), green (0.3, 0.6), blue (0.15, 0.06)",
+        ]);
+    }
+
+    #[test]
+    fn test_describe_invalid_chrm() {
+        let png = Png::from_chunks(vec![chunk("cHRM", vec![0; 31])]);
+        assert_eq!(describe(&png), vec!["cHRM: invalid (cHRM must be 32 bytes)"]);
+    }
+
+    fn ihdr(color_type: u8) -> Chunk {
+        chunk("IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, color_type, 0, 0, 0])
+    }
+
+    #[test]
+    fn test_describe_trns_palette() {
+        let png = Png::from_chunks(vec![
+            ihdr(3),
+            chunk("PLTE", vec![0; 9]),
+            chunk("tRNS", vec![255, 128, 0]),
+        ]);
+        assert_eq!(describe(&png), vec!["tRNS: palette alpha [255, 128, 0]"]);
+
+        let png = Png::from_chunks(vec![
+            ihdr(3),
+            chunk("PLTE", vec![0; 6]),
+            chunk("tRNS", vec![255, 128, 0]),
+        ]);
+        assert_eq!(describe(&png), vec!["tRNS: invalid (tRNS has more entries than the palette)"]);
+    }
+
+    #[test]
+    fn test_describe_trns_truecolor() {
+        let png = Png::from_chunks(vec![ihdr(2), chunk("tRNS", vec![0, 1, 1, 0, 255, 255])]);
+        assert_eq!(describe(&png), vec!["tRNS: rgb key (1, 256, 65535)"]);
+
+        let png = Png::from_chunks(vec![ihdr(2), chunk("tRNS", vec![0, 1])]);
+        assert_eq!(describe(&png), vec!["tRNS: invalid (tRNS must be 6 bytes for truecolor images)"]);
+    }
+
+    #[test]
+    fn test_describe_trns_grayscale_and_alpha() {
+        let png = Png::from_chunks(vec![ihdr(0), chunk("tRNS", vec![0, 7])]);
+        assert_eq!(describe(&png), vec!["tRNS: gray key 7"]);
+
+        let png = Png::from_chunks(vec![ihdr(6), chunk("tRNS", vec![0, 7])]);
+        assert_eq!(describe(&png), vec!["tRNS: invalid (tRNS is not allowed for images with an alpha channel)"]);
+    }
+
+    #[test]
+    fn test_describe_sbit() {
+        let png = Png::from_chunks(vec![ihdr(2), chunk("sBIT", vec![5, 6, 5])]);
+        assert_eq!(describe(&png), vec!["sBIT: significant bits red 5, green 6, blue 5"]);
+
+        let png = Png::from_chunks(vec![ihdr(4), chunk("sBIT", vec![3, 8])]);
+        assert_eq!(describe(&png), vec!["sBIT: significant bits gray 3, alpha 8"]);
+
+        let png = Png::from_chunks(vec![ihdr(6), chunk("sBIT", vec![5, 6, 5])]);
+        assert_eq!(describe(&png), vec!["sBIT: invalid (sBIT length doesn't match the color type)"]);
+
+        // more bits than the 8 bit depth of `ihdr`
+        let png = Png::from_chunks(vec![ihdr(0), chunk("sBIT", vec![9])]);
+        assert_eq!(describe(&png), vec!["sBIT: invalid (significant bits must be between 1 and the sample depth)"]);
+    }
+
+    #[test]
+    fn test_describe_hist() {
+        let png = Png::from_chunks(vec![
+            ihdr(3),
+            chunk("PLTE", vec![0; 6]),
+            chunk("hIST", vec![0, 1, 1, 0]),
+        ]);
+        assert_eq!(describe(&png), vec!["hIST: palette histogram [1, 256]"]);
+
+        let png = Png::from_chunks(vec![
+            ihdr(3),
+            chunk("PLTE", vec![0; 9]),
+            chunk("hIST", vec![0, 1, 1, 0]),
+        ]);
+        assert_eq!(describe(&png), vec!["hIST: invalid (hIST must have one entry per palette entry)"]);
+
+        let png = Png::from_chunks(vec![ihdr(2), chunk("hIST", vec![0, 1])]);
+        assert_eq!(describe(&png), vec!["hIST: invalid (hIST requires a PLTE chunk)"]);
+    }
+
+    #[test]
+    fn test_describe_actl() {
+        let png = Png::from_chunks(vec![
+            ihdr(6),
+            chunk("acTL", vec![0, 0, 0, 2, 0, 0, 0, 0]),
+            chunk("fcTL", vec![0; 26]),
+            chunk("IDAT", vec![]),
+            chunk("fcTL", vec![0; 26]),
+            chunk("fdAT", vec![0; 4]),
+        ]);
+        assert_eq!(describe(&png), vec!["acTL: animated, 2 frame(s), plays infinite, 2 fcTL and 1 fdAT chunk(s)"]);
+
+        let png = Png::from_chunks(vec![chunk("acTL", vec![0, 0, 0, 1, 0, 0, 0, 3])]);
+        assert_eq!(describe(&png), vec!["acTL: animated, 1 frame(s), plays 3, 0 fcTL and 0 fdAT chunk(s)"]);
+    }
+
+    #[test]
+    fn test_describe_invalid_actl() {
+        let png = Png::from_chunks(vec![
+            chunk("acTL", vec![0, 0, 0, 1]),
+            chunk("acTL", vec![0; 8]),
+        ]);
+        assert_eq!(describe(&png), vec![
+            "acTL: invalid (acTL must be 8 bytes)",
+            "acTL: invalid (acTL must have at least one frame)",
+        ]);
+    }
+
+    #[test]
+    fn test_describe_offs() {
+        let data = [(-5i32).to_be_bytes(), 300i32.to_be_bytes()].concat();
+
+        let png = Png::from_chunks(vec![
+            chunk("oFFs", [&data[..], &[1]].concat()),
+            chunk("oFFs", [&data[..], &[0]].concat()),
+            chunk("oFFs", [&data[..], &[2]].concat()),
+            chunk("oFFs", data),
+        ]);
+        assert_eq!(describe(&png), vec![
+            "oFFs: offset (-5, 300) micrometres",
+            "oFFs: offset (-5, 300) pixels",
+            "oFFs: invalid (unknown oFFs unit)",
+            "oFFs: invalid (oFFs must be 9 bytes)",
+        ]);
+    }
+
+    #[test]
+    fn test_describe_scal() {
+        let png = Png::from_chunks(vec![
+            chunk("sCAL", b"\x011.5e-3\x000.002".to_vec()),
+            chunk("sCAL", b"\x0290\x00.5".to_vec()),
+        ]);
+        assert_eq!(describe(&png), vec![
+            "sCAL: pixel size 0.0015 x 0.002 metres",
+            "sCAL: pixel size 90 x 0.5 radians",
+        ]);
+    }
+
+    #[test]
+    fn test_describe_invalid_scal() {
+        let png = Png::from_chunks(vec![
+            chunk("sCAL", b"\x011.5\x00inf".to_vec()),
+            chunk("sCAL", b"\x011..5\x002".to_vec()),
+            chunk("sCAL", b"\x011.5\x00".to_vec()),
+            chunk("sCAL", b"\x011.5".to_vec()),
+            chunk("sCAL", b"\x01-1\x002".to_vec()),
+            chunk("sCAL", b"\x031\x002".to_vec()),
+            chunk("sCAL", vec![]),
+        ]);
+        assert_eq!(describe(&png), vec![
+            "sCAL: invalid (sCAL value is not a floating-point number)",
+            "sCAL: invalid (sCAL value is not a floating-point number)",
+            "sCAL: invalid (sCAL value is not a floating-point number)",
+            "sCAL: invalid (sCAL must have a width and a height)",
+            "sCAL: invalid (sCAL width and height must be greater than zero)",
+            "sCAL: invalid (unknown sCAL unit)",
+            "sCAL: invalid (sCAL must have a unit)",
+        ]);
+    }
+
+    #[test]
+    fn test_describe_skips_unknown_chunks() {
+        let png = Png::from_chunks(vec![chunk("ruSt", b"secret".to_vec())]);
+        assert!(describe(&png).is_empty());
+    }
+}
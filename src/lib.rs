@@ -0,0 +1,7 @@
+pub mod chunk;
+pub mod chunk_type;
+pub mod error;
+pub mod png;
+mod zlib;
+
+pub use error::{Error, Result};
@@ -1,11 +1,9 @@
-pub mod chunk;
-pub mod chunk_type;
-pub mod png;
+// the PNG types live in the library crate, which also builds on its own without the `cli` feature
+use pngme::{chunk, chunk_type, png, Error, Result};
+
 mod args;
 mod commands;
-
-type Error = &'static str;
-type Result<T> = std::result::Result<T, Error>;
+mod info;
 
 fn main() {
     args::process_args();
@@ -1,113 +1,820 @@
-use crate::chunk::Chunk;
+use crate::chunk::{Chunk, ChunkRef};
+use crate::chunk_type::ChunkType;
 use std::convert::TryFrom;
-use crate::{Error, Result};
+use std::io::Read;
+use std::ops::ControlFlow;
+use crate::{zlib, Error, Result};
+
+// Which chunks have their crc validated while parsing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CrcPolicy {
+    CriticalOnly,
+    All,
+    None,
+}
+
+impl CrcPolicy {
+    pub fn should_validate(&self, chunk: &Chunk) -> bool {
+        self.should_validate_type(chunk.chunk_type())
+    }
+
+    pub fn should_validate_type(&self, chunk_type: &ChunkType) -> bool {
+        match self {
+            CrcPolicy::CriticalOnly => chunk_type.is_critical(),
+            CrcPolicy::All => true,
+            CrcPolicy::None => false,
+        }
+    }
+}
+
+impl std::str::FromStr for CrcPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "critical-only" => Ok(CrcPolicy::CriticalOnly),
+            "all" => Ok(CrcPolicy::All),
+            "none" => Ok(CrcPolicy::None),
+            _ => Err("Invalid crc policy"),
+        }
+    }
+}
+
+// Far more chunks than any real image has, while keeping untrusted input from exhausting memory
+pub const DEFAULT_MAX_CHUNKS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseLimits {
+    pub max_chunks: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_chunks: DEFAULT_MAX_CHUNKS,
+        }
+    }
+}
+
+// Summary of a single chunk, as reported by `Png::describe`
+#[derive(Debug, PartialEq)]
+pub struct ChunkSummary {
+    pub chunk_type: String,
+    pub length: u32,
+    pub crc: u32,
+    pub critical: bool,
+    pub public: bool,
+    pub safe_to_copy: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PngDescription {
+    pub chunks: Vec<ChunkSummary>,
+}
+
+// length (4 bytes) + type (4 bytes) + crc (4 bytes)
+const MIN_CHUNK_LEN: usize = 12;
+
+// The 8 bytes every PNG file starts with, emitted by `Png::as_bytes` and `Png::write_to`
+pub const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+// Every chunk type defined by the PNG specification, including the APNG extension
+// and the registered extension chunks
+pub const KNOWN_CHUNK_TYPES: [&str; 34] = [
+    "IHDR", "PLTE", "IDAT", "IEND", "cHRM", "cICP", "gAMA", "iCCP", "mDCV", "cLLI", "sBIT", "sRGB",
+    "bKGD", "hIST", "tRNS", "eXIf", "pHYs", "sPLT", "tIME", "iTXt", "tEXt", "zTXt", "acTL", "fcTL",
+    "fdAT", "oFFs", "pCAL", "sCAL", "sTER", "gIFg", "gIFx", "gIFt", "fRAc", "dSIG",
+];
+
+pub fn is_known_chunk_type(chunk_type: &str) -> bool {
+    KNOWN_CHUNK_TYPES.contains(&chunk_type)
+}
+
+// Why `Png::parse_body` failed and the file offset of the chunk it failed on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseFailure {
+    pub offset: usize,
+    pub error: Error,
+}
+
+// Result of `Png::validate_signature_and_collect`, the chunk fields describe whatever could be parsed
+#[derive(Debug, PartialEq)]
+pub struct HealthReport {
+    pub signature_valid: bool,
+    pub ihdr_present: bool,
+    pub ihdr_first: bool,
+    pub iend_present: bool,
+    pub iend_last: bool,
+    pub crc_errors: usize,
+    // chunk types not in `KNOWN_CHUNK_TYPES`
+    pub unknown_chunks: usize,
+    pub trailing_bytes: usize,
+    // why the chunks couldn't be parsed, if they couldn't
+    pub parse_error: Option<Error>,
+}
 
 pub struct Png {
     chunks: Vec<Chunk>,
+    // anything stored after the `IEND` chunk that isn't itself a chunk
+    trailing: Vec<u8>,
 }
 
 impl Png {
-    pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+    pub const STANDARD_HEADER: [u8; 8] = PNG_SIGNATURE;
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
-        Png { chunks: chunks }
+        Png {
+            chunks,
+            trailing: vec![],
+        }
     }
-    pub fn append_chunk(&mut self, chunk: Chunk) {
+    // Returns the index of the appended chunk
+    pub fn append_chunk(&mut self, chunk: Chunk) -> usize {
         self.chunks.push(chunk);
+        self.chunks.len() - 1
+    }
+    // Like `append_chunk`, but a PNG can only have one `IHDR` and one `IEND`
+    pub fn append_chunk_checked(&mut self, chunk: Chunk) -> Result<usize> {
+        let chunk_type = chunk.chunk_type().as_str();
+
+        if (chunk_type == "IHDR" || chunk_type == "IEND") && self.contains_chunk_type(&chunk_type) {
+            return Err("duplicate IHDR or IEND chunk");
+        }
+
+        Ok(self.append_chunk(chunk))
+    }
+    // Inserts in front of the first `reference_type` chunk, returning the new chunk's index
+    pub fn insert_before(&mut self, reference_type: &str, chunk: Chunk) -> Result<usize> {
+        match self.chunks.iter().position(|c| c.chunk_type().eq_str(reference_type)) {
+            Some(i) => {
+                self.chunks.insert(i, chunk);
+                Ok(i)
+            }
+            None => Err("reference chunk type not found"),
+        }
+    }
+    // Inserts behind the last `reference_type` chunk, so that e.g. every `IDAT` stays together
+    pub fn insert_after(&mut self, reference_type: &str, chunk: Chunk) -> Result<usize> {
+        match self.chunks.iter().rposition(|c| c.chunk_type().eq_str(reference_type)) {
+            Some(i) => {
+                self.chunks.insert(i + 1, chunk);
+                Ok(i + 1)
+            }
+            None => Err("reference chunk type not found"),
+        }
     }
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
         for i in 0..self.chunks.len() {
-            if self.chunks[i].chunk_type().to_string() == chunk_type {
+            if self.chunks[i].chunk_type().as_str() == chunk_type {
                 return Ok(self.chunks.remove(i));
             }
         }
 
         Err("chunk not found")
     }
+    // Returns the removed chunks along with the index each one had before removal
+    pub fn remove_all_chunks(&mut self, chunk_type: &str) -> Vec<(usize, Chunk)> {
+        self.remove_chunks_where(|chunk| chunk.chunk_type().as_str() == chunk_type)
+    }
+    // Removes every chunk whose type isn't in `KNOWN_CHUNK_TYPES`, such as private ones
+    pub fn remove_unknown_chunks(&mut self) -> Vec<(usize, Chunk)> {
+        self.remove_chunks_where(|chunk| !is_known_chunk_type(&chunk.chunk_type().as_str()))
+    }
+    /*
+    Swaps every `chunk_type` chunk for `chunks`, placed where the first one was,
+    or in front of `IEND` (else at the end) when there were none
+    */
+    pub fn replace_all(&mut self, chunk_type: &str, chunks: Vec<Chunk>) {
+        let removed = self.remove_all_chunks(chunk_type);
+
+        let at = match removed.first() {
+            Some((i, _)) => *i,
+            None => self
+                .chunks
+                .iter()
+                .position(|c| c.chunk_type().eq_str("IEND"))
+                .unwrap_or(self.chunks.len()),
+        };
+
+        self.chunks.splice(at..at, chunks);
+    }
+    /*
+    Sorts ancillary chunks by type for reproducible output, critical chunks stay
+    where they are. Ancillary chunks are only sorted among those between the same
+    two critical chunks, so none moves across `PLTE` or `IDAT`
+    */
+    pub fn sort_ancillary_stable(&mut self) {
+        for run in self.chunks.split_mut(|chunk| chunk.chunk_type().is_critical()) {
+            run.sort_by_key(|chunk| chunk.chunk_type().bytes());
+        }
+    }
+    // `remove` sees the chunks in order, so it can count matches to remove only some of them
+    pub fn remove_chunks_where<F: FnMut(&Chunk) -> bool>(&mut self, mut remove: F) -> Vec<(usize, Chunk)> {
+        let mut removed = vec![];
+        let mut kept = vec![];
+
+        for (i, chunk) in self.chunks.drain(..).enumerate() {
+            if remove(&chunk) {
+                removed.push((i, chunk));
+            } else {
+                kept.push(chunk);
+            }
+        }
+
+        self.chunks = kept;
+
+        removed
+    }
+    // Tells apart chunks of the same type, the stored crc is compared even when it's invalid
+    pub fn remove_chunk_by_crc(&mut self, crc: u32, chunk_type: Option<&str>) -> Result<Chunk> {
+        let index = self.chunks.iter().position(|chunk| {
+            let type_matches = match chunk_type {
+                Some(chunk_type) => chunk.chunk_type().eq_str(chunk_type),
+                None => true,
+            };
+
+            chunk.crc() == crc && type_matches
+        });
+
+        match index {
+            Some(i) => Ok(self.chunks.remove(i)),
+            None => Err("chunk not found"),
+        }
+    }
+    // Keeps only the chunks `keep` returns true for, in order, returning how many were removed
+    pub fn retain_chunks<F: FnMut(&Chunk) -> bool>(&mut self, keep: F) -> usize {
+        let len = self.chunks.len();
+        self.chunks.retain(keep);
+        len - self.chunks.len()
+    }
+    pub fn remove_chunk_at(&mut self, index: usize) -> Result<Chunk> {
+        if index >= self.chunks.len() {
+            return Err("chunk index out of range");
+        }
+
+        Ok(self.chunks.remove(index))
+    }
+    pub fn trailing_bytes(&self) -> &[u8] {
+        &self.trailing
+    }
+    pub fn set_trailing_bytes(&mut self, trailing: Vec<u8>) {
+        self.trailing = trailing;
+    }
     pub fn header(&self) -> &[u8; 8] {
-        return &Png::STANDARD_HEADER;
+        &PNG_SIGNATURE
+    }
+    pub fn has_valid_signature(arr: &[u8]) -> bool {
+        arr.starts_with(&PNG_SIGNATURE)
     }
     pub fn chunks(&self) -> &[Chunk] {
         &self.chunks
     }
+
+    pub fn chunks_mut(&mut self) -> &mut [Chunk] {
+        &mut self.chunks
+    }
+    // Each chunk's type in file order, handy as a fingerprint of the structure
+    pub fn chunk_types(&self) -> impl Iterator<Item = ChunkType> + '_ {
+        self.chunks.iter().map(|chunk| *chunk.chunk_type())
+    }
+    pub fn contains_chunk_type(&self, chunk_type: &str) -> bool {
+        self.chunks.iter().any(|chunk| chunk.chunk_type().eq_str(chunk_type))
+    }
+    pub fn chunk_count_by_type(&self, chunk_type: &str) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.chunk_type().eq_str(chunk_type)).count()
+    }
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
-        for chunk in &self.chunks {
-            if chunk.chunk_type().to_string() == chunk_type {
-                return Some(&chunk);
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type().as_str() == chunk_type)
+    }
+    pub fn chunk_by_type_mut(&mut self, chunk_type: &str) -> Option<&mut Chunk> {
+        self.chunks
+            .iter_mut()
+            .find(|chunk| chunk.chunk_type().as_str() == chunk_type)
+    }
+    /*
+    The structure every viewer can rely on: `IHDR` first, `IEND` last,
+    at least one `IDAT`, at most one `IHDR`, `PLTE` and `IEND`, and nothing
+    that breaks `order_violations`
+    */
+    pub fn validate_structure(&self) -> Result<()> {
+        if !self.chunks.first().is_some_and(|chunk| chunk.chunk_type().eq_str("IHDR")) {
+            return Err("IHDR must be the first chunk");
+        }
+
+        if !self.chunks.last().is_some_and(|chunk| chunk.chunk_type().eq_str("IEND")) {
+            return Err("IEND must be the last chunk");
+        }
+
+        if !self.contains_chunk_type("IDAT") {
+            return Err("missing IDAT chunk");
+        }
+
+        if ["IHDR", "PLTE", "IEND"].iter().any(|t| self.chunk_count_by_type(t) > 1) {
+            return Err("duplicate IHDR, PLTE or IEND chunk");
+        }
+
+        if !self.order_violations().is_empty() {
+            return Err("chunks are out of order");
+        }
+
+        Ok(())
+    }
+    // Chunks whose stored crc doesn't match their type and data, only possible with a lenient `CrcPolicy`
+    pub fn crc_errors(&self) -> Vec<(usize, &Chunk)> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| !chunk.has_valid_crc())
+            .collect()
+    }
+    /*
+    Chunks that break the ordering rules of the PNG specification, with the rule they break
+
+    Chunk types without rules of their own, such as text and private chunks,
+    only have to come after IHDR and before IEND
+    */
+    pub fn order_violations(&self) -> Vec<(usize, &'static str)> {
+        let position = |chunk_type: &str| self.chunks.iter().position(|chunk| chunk.chunk_type().as_str() == chunk_type);
+        let plte = position("PLTE");
+        let first_idat = position("IDAT");
+
+        let mut violations = vec![];
+        let mut seen_iend = false;
+        let mut seen_idat = false;
+        let mut idat_interrupted = false;
+
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            let chunk_type = chunk.chunk_type().as_str();
+            let comes_after = |other: Option<usize>| other.is_some_and(|other| i > other);
+            let comes_before = |other: Option<usize>| other.is_some_and(|other| i < other);
+
+            let violation = match &*chunk_type {
+                _ if seen_iend => Some("comes after IEND"),
+                "IHDR" if i != 0 => Some("must be the first chunk"),
+                "IDAT" if idat_interrupted => Some("IDAT chunks must be consecutive"),
+                "cHRM" | "cICP" | "gAMA" | "iCCP" | "mDCV" | "cLLI" | "sBIT" | "sRGB" if comes_after(plte) => {
+                    Some("must come before PLTE")
+                }
+                "bKGD" | "hIST" | "tRNS" if comes_before(plte) => Some("must come after PLTE"),
+                "cHRM" | "cICP" | "gAMA" | "iCCP" | "mDCV" | "cLLI" | "sBIT" | "sRGB" | "bKGD" | "hIST" | "tRNS"
+                | "PLTE" | "pHYs" | "sPLT" | "eXIf" | "acTL"
+                    if comes_after(first_idat) =>
+                {
+                    Some("must come before IDAT")
+                }
+                _ => None,
+            };
+
+            if let Some(violation) = violation {
+                violations.push((i, violation));
+            }
+
+            seen_iend |= chunk_type == "IEND";
+            idat_interrupted |= seen_idat && chunk_type != "IDAT";
+            seen_idat |= chunk_type == "IDAT";
+        }
+
+        violations
+    }
+    // The compressed image stream, split across every `IDAT` chunk
+    pub fn image_data(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type().as_str() == "IDAT")
+            .flat_map(|chunk| chunk.data().iter().copied())
+            .collect()
+    }
+    // Inflates the image stream without unfiltering it
+    pub fn decompressed_image_data(&self) -> Result<Vec<u8>> {
+        if !self.contains_chunk_type("IDAT") {
+            return Err("no IDAT chunks");
+        }
+
+        zlib::inflate(&self.image_data())
+    }
+    // The stored and the computed Adler-32 of the image stream, they differ when the pixels are corrupt
+    pub fn image_data_adler32(&self) -> Result<(u32, u32)> {
+        if !self.contains_chunk_type("IDAT") {
+            return Err("no IDAT chunks");
+        }
+
+        zlib::adler32_check(&self.image_data())
+    }
+    /*
+    Size `decompressed_image_data` should have according to `IHDR`: every row
+    of every pass is a filter byte followed by its pixels, packed to whole bytes
+    */
+    pub fn expected_image_data_len(&self) -> Result<usize> {
+        let ihdr = match self.chunk_by_type("IHDR") {
+            Some(chunk) if chunk.data().len() == 13 => chunk.data(),
+            Some(_) => return Err("IHDR must be 13 bytes"),
+            None => return Err("no IHDR chunk"),
+        };
+
+        let width = u32::from_be_bytes([ihdr[0], ihdr[1], ihdr[2], ihdr[3]]) as u64;
+        let height = u32::from_be_bytes([ihdr[4], ihdr[5], ihdr[6], ihdr[7]]) as u64;
+        let (bit_depth, color_type, interlace) = (ihdr[8] as u64, ihdr[9], ihdr[12]);
+
+        let channels = match color_type {
+            0 | 3 => 1,
+            4 => 2,
+            2 => 3,
+            6 => 4,
+            _ => return Err("unknown color type"),
+        };
+
+        // (x offset, y offset, x step, y step) of each Adam7 pass
+        let passes: &[(u64, u64, u64, u64)] = match interlace {
+            0 => &[(0, 0, 1, 1)],
+            1 => &[(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)],
+            _ => return Err("unknown interlace method"),
+        };
+
+        let pass_len = |&(x0, y0, dx, dy): &(u64, u64, u64, u64)| {
+            let pass_width = (width + dx - 1).saturating_sub(x0) / dx;
+            let pass_height = (height + dy - 1).saturating_sub(y0) / dy;
+
+            if pass_width == 0 {
+                return 0;
             }
+
+            pass_height * (1 + (pass_width * channels * bit_depth).div_ceil(8))
+        };
+
+        match usize::try_from(passes.iter().map(pass_len).sum::<u64>()) {
+            Ok(len) => Ok(len),
+            Err(_) => Err("image is too large"),
         }
+    }
+    pub fn describe(&self) -> PngDescription {
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|chunk| ChunkSummary {
+                chunk_type: chunk.chunk_type().to_string(),
+                length: chunk.length(),
+                crc: chunk.crc(),
+                critical: chunk.chunk_type().is_critical(),
+                public: chunk.chunk_type().is_public(),
+                safe_to_copy: chunk.chunk_type().is_safe_to_copy(),
+            })
+            .collect();
 
-        None
+        PngDescription { chunks }
+    }
+    // Every chunk's data rendered as text and joined together, including binary chunks
+    pub fn concatenated_text(&self) -> String {
+        self.chunks.iter().map(|chunk| chunk.to_string()).collect()
+    }
+    // Length of `as_bytes` without serializing
+    pub fn byte_size(&self) -> usize {
+        PNG_SIGNATURE.len() + self.chunks.iter().map(Chunk::total_len).sum::<usize>() + self.trailing.len()
     }
     pub fn as_bytes(&self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = vec![];
+        let mut bytes: Vec<u8> = Vec::with_capacity(self.byte_size());
 
         // header
-        bytes.extend_from_slice(&Png::STANDARD_HEADER[..]);
+        bytes.extend_from_slice(&PNG_SIGNATURE[..]);
 
         // chunks
         for chunk in self.chunks.iter() {
             bytes.extend_from_slice(&Chunk::as_bytes(chunk)[..]);
         }
 
+        // trailing bytes
+        bytes.extend_from_slice(&self.trailing[..]);
+
         bytes
     }
+    // Nothing is written unless `validate_structure` passes, `write_to` skips the check
+    pub fn to_writer_checked<W: std::io::Write>(&self, w: &mut W) -> Result<()> {
+        self.validate_structure()?;
+
+        match self.write_to(w) {
+            Ok(()) => Ok(()),
+            Err(_) => Err("error writing png"),
+        }
+    }
+    pub fn write_to<W: std::io::Write>(&self, w: &mut W) -> std::io::Result<()> {
+        w.write_all(&PNG_SIGNATURE)?;
+
+        for chunk in self.chunks.iter() {
+            w.write_all(&chunk.as_bytes())?;
+        }
+
+        w.write_all(&self.trailing)?;
+
+        Ok(())
+    }
 }
 
 impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(arr: &[u8]) -> Result<Self> {
-        let mut iter = arr.iter();
+        Png::try_from_with_policy(arr, CrcPolicy::All)
+    }
+}
 
-        // header
-        let mut header: [u8; 8] = [0; 8];
+impl Png {
+    pub fn try_from_with_policy(arr: &[u8], crc_policy: CrcPolicy) -> Result<Png> {
+        Png::try_from_with_limits(arr, crc_policy, ParseLimits::default())
+    }
 
-        for i in 0..8 {
-            header[i] = match iter.next() {
-                Some(i) => *i,
-                None => return Err("ran out of bytes reading png header"),
-            };
+    pub fn try_from_with_limits(arr: &[u8], crc_policy: CrcPolicy, limits: ParseLimits) -> Result<Png> {
+        Png::check_signature(arr)?;
+
+        Png::parse_body(&arr[PNG_SIGNATURE.len()..], crc_policy, limits).map_err(|failure| failure.error)
+    }
+
+    // The first stage of parsing, a file that passes has at least the 8 signature bytes
+    pub fn check_signature(arr: &[u8]) -> Result<()> {
+        if arr.len() < PNG_SIGNATURE.len() {
+            return Err("ran out of bytes reading png header");
         }
 
-        if header != Png::STANDARD_HEADER {
+        if !Png::has_valid_signature(arr) {
             return Err("invalid png header");
         }
 
-        // chunks
-        let mut chunks: Vec<Chunk> = vec![];
-        let mut i: usize = 8;
+        Ok(())
+    }
+
+    /*
+    Reads a PNG from `reader` and hands `f` one chunk at a time, so at most one
+    chunk's data is in memory. Every crc is validated, like `Png::try_from`
+
+    Stops after `IEND`, at the end of the input, or when `f` breaks, anything
+    after `IEND` isn't read
+    */
+    pub fn stream_chunks<R: Read, F: FnMut(Chunk) -> ControlFlow<()>>(mut reader: R, mut f: F) -> Result<()> {
+        let mut signature = [0; 8];
+        let read = Png::read_up_to(&mut reader, &mut signature)?;
+        Png::check_signature(&signature[..read])?;
+
+        loop {
+            // length and type
+            let mut header = [0; 8];
+
+            match Png::read_up_to(&mut reader, &mut header)? {
+                0 => return Ok(()),
+                8 => {}
+                _ => return Err("ran out of bytes reading chunk"),
+            }
+
+            let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+            let chunk_type = ChunkType::try_from([header[4], header[5], header[6], header[7]])?;
+
+            // grows as the data arrives, so a corrupt length doesn't allocate up front
+            let mut data = vec![];
+            let mut crc = [0; 4];
+
+            if (&mut reader).take(length as u64).read_to_end(&mut data).is_err()
+                || data.len() != length as usize
+                || Png::read_up_to(&mut reader, &mut crc)? != crc.len()
+            {
+                return Err("ran out of bytes reading chunk");
+            }
+
+            let chunk = Chunk::new(chunk_type, data);
+
+            if !chunk.crc_matches(u32::from_be_bytes(crc)) {
+                return Err("error reading chunk");
+            }
+
+            let is_iend = chunk_type.bytes() == *b"IEND";
+
+            if f(chunk).is_break() || is_iend {
+                return Ok(());
+            }
+        }
+    }
+
+    // Like `read_exact`, but returns how much was read when the input ends first
+    fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+        let mut read = 0;
+
+        while read < buf.len() {
+            match reader.read(&mut buf[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => return Err("error reading png"),
+            }
+        }
+
+        Ok(read)
+    }
+
+    /*
+    The second stage of parsing, reads the chunks that follow a valid signature
+
+    Failure offsets count from the start of the file, so a malformed first chunk is at offset 8
+    */
+    pub fn parse_body(body: &[u8], crc_policy: CrcPolicy, limits: ParseLimits) -> std::result::Result<Png, ParseFailure> {
+        PngRef::parse_chunks(body, crc_policy, limits)
+            .map(|png| png.to_png())
+            .map_err(|failure| ParseFailure {
+                offset: failure.offset + PNG_SIGNATURE.len(),
+                ..failure
+            })
+    }
+
+    /*
+    Runs every structural check at once for quick triage, never failing
+
+    Chunks are parsed without crc validation so that bad crcs can be counted
+    */
+    pub fn validate_signature_and_collect(arr: &[u8]) -> HealthReport {
+        let body = arr.get(PNG_SIGNATURE.len()..).unwrap_or(&[]);
+
+        let (png, parse_error) = match Png::parse_chunks(body, CrcPolicy::None, ParseLimits::default()) {
+            Ok(png) => (png, None),
+            Err(err) => (Png::from_chunks(vec![]), Some(err)),
+        };
+
+        let is_type = |chunk: Option<&Chunk>, chunk_type: &str| {
+            chunk.is_some_and(|chunk| chunk.chunk_type().as_str() == chunk_type)
+        };
+
+        HealthReport {
+            signature_valid: Png::has_valid_signature(arr),
+            ihdr_present: png.contains_chunk_type("IHDR"),
+            ihdr_first: is_type(png.chunks.first(), "IHDR"),
+            iend_present: png.contains_chunk_type("IEND"),
+            iend_last: is_type(png.chunks.last(), "IEND"),
+            crc_errors: png.crc_errors().len(),
+            unknown_chunks: png
+                .chunks
+                .iter()
+                .filter(|chunk| !is_known_chunk_type(&chunk.chunk_type().as_str()))
+                .count(),
+            trailing_bytes: png.trailing.len(),
+            parse_error,
+        }
+    }
+
+    // For chunk streams exported without the signature, which `as_bytes` adds back
+    pub fn try_from_raw_with_policy(arr: &[u8], crc_policy: CrcPolicy) -> Result<Png> {
+        Png::parse_chunks(arr, crc_policy, ParseLimits::default())
+    }
+
+    // Offsets in the failure are from the start of `arr`
+    pub fn parse_raw_body(arr: &[u8], crc_policy: CrcPolicy, limits: ParseLimits) -> std::result::Result<Png, ParseFailure> {
+        PngRef::parse_chunks(arr, crc_policy, limits).map(|png| png.to_png())
+    }
+
+    fn parse_chunks(arr: &[u8], crc_policy: CrcPolicy, limits: ParseLimits) -> Result<Png> {
+        Png::parse_raw_body(arr, crc_policy, limits).map_err(|failure| failure.error)
+    }
+}
+
+/*
+A read-only `Png` whose chunks borrow their data from the input buffer,
+so that reading a file doesn't copy every `IDAT` chunk
+
+`to_png` gives an owned copy that can be changed
+*/
+pub struct PngRef<'a> {
+    chunks: Vec<ChunkRef<'a>>,
+    trailing: &'a [u8],
+}
+
+impl<'a> PngRef<'a> {
+    pub fn try_from_with_policy(arr: &'a [u8], crc_policy: CrcPolicy) -> Result<PngRef<'a>> {
+        PngRef::try_from_with_limits(arr, crc_policy, ParseLimits::default())
+    }
+    pub fn try_from_with_limits(arr: &'a [u8], crc_policy: CrcPolicy, limits: ParseLimits) -> Result<PngRef<'a>> {
+        Png::check_signature(arr)?;
+
+        PngRef::parse_chunks(&arr[PNG_SIGNATURE.len()..], crc_policy, limits).map_err(|failure| failure.error)
+    }
+    pub fn try_from_raw_with_policy(arr: &'a [u8], crc_policy: CrcPolicy) -> Result<PngRef<'a>> {
+        PngRef::parse_chunks(arr, crc_policy, ParseLimits::default()).map_err(|failure| failure.error)
+    }
+    pub fn chunks(&self) -> &[ChunkRef<'a>] {
+        &self.chunks
+    }
+    pub fn trailing_bytes(&self) -> &'a [u8] {
+        self.trailing
+    }
+    pub fn chunk_types(&self) -> impl Iterator<Item = ChunkType> + '_ {
+        self.chunks.iter().map(|chunk| *chunk.chunk_type())
+    }
+    pub fn contains_chunk_type(&self, chunk_type: &str) -> bool {
+        self.chunks.iter().any(|chunk| chunk.chunk_type().eq_str(chunk_type))
+    }
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&ChunkRef<'a>> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type().eq_str(chunk_type))
+    }
+    pub fn byte_size(&self) -> usize {
+        PNG_SIGNATURE.len() + self.chunks.iter().map(ChunkRef::total_len).sum::<usize>() + self.trailing.len()
+    }
+    pub fn to_png(&self) -> Png {
+        let mut png = Png::from_chunks(self.chunks.iter().map(ChunkRef::to_chunk).collect());
+        png.set_trailing_bytes(self.trailing.to_vec());
+        png
+    }
+
+    fn parse_chunks(arr: &'a [u8], crc_policy: CrcPolicy, limits: ParseLimits) -> std::result::Result<PngRef<'a>, ParseFailure> {
+        let fail = |offset, error| Err(ParseFailure { offset, error });
+
+        let mut chunks: Vec<ChunkRef> = vec![];
+        let mut i: usize = 0;
+        let mut seen_iend = false;
+        let mut trailing: &[u8] = &[];
 
         while i < arr.len() {
-            let chunk_len = match Chunk::get_total_length_from_bytes(&arr[i..]) {
-                Ok(len) => len as usize,
-                Err(_) => return Err("error precalculating size of chunk"),
-            };
+            // too short to hold even an empty chunk, before IEND the file is truncated
+            if arr.len() - i < MIN_CHUNK_LEN {
+                if !seen_iend {
+                    return fail(i, "ran out of bytes reading chunk");
+                }
+
+                trailing = &arr[i..];
+                break;
+            }
+
+            match PngRef::read_chunk(&arr[i..], crc_policy) {
+                Ok(_) if chunks.len() == limits.max_chunks => return fail(i, "too many chunks"),
+                Ok((chunk, chunk_len)) => {
+                    seen_iend |= chunk.chunk_type().eq_str("IEND");
+                    chunks.push(chunk);
+                    i += chunk_len;
+                }
+                // anything after IEND that doesn't parse as a chunk is kept as trailing bytes
+                Err(_) if seen_iend => {
+                    trailing = &arr[i..];
+                    break;
+                }
+                Err(err) => return fail(i, err),
+            }
+        }
+
+        Ok(PngRef { chunks, trailing })
+    }
 
-            chunks.push(match Chunk::try_from(&arr[i..i + chunk_len]) {
-                Ok(chunk) => chunk,
-                Err(_) => return Err("error reading chunk"),
-            });
+    // Reads the first chunk in `arr`, returning it along with its total length in bytes
+    fn read_chunk(arr: &'a [u8], crc_policy: CrcPolicy) -> Result<(ChunkRef<'a>, usize)> {
+        let chunk_len = match Chunk::get_total_length_from_bytes(arr) {
+            Ok(len) => len as usize,
+            Err(_) => return Err("error precalculating size of chunk"),
+        };
 
-            i += chunk_len;
+        if chunk_len > arr.len() {
+            return Err("ran out of bytes reading chunk");
         }
 
-        Ok(Png::from_chunks(chunks))
+        let chunk = match ChunkRef::try_from_unchecked(&arr[..chunk_len]) {
+            Ok(chunk) => chunk,
+            Err(_) => return Err("error reading chunk"),
+        };
+
+        if crc_policy.should_validate_type(chunk.chunk_type())
+            && Chunk::compute_crc(chunk.chunk_type(), chunk.data()) != chunk.crc()
+        {
+            return Err("error reading chunk");
+        }
+
+        Ok((chunk, chunk_len))
     }
 }
 
-impl std::fmt::Display for Png {
+impl<'a> TryFrom<&'a [u8]> for PngRef<'a> {
+    type Error = Error;
+
+    fn try_from(arr: &'a [u8]) -> Result<Self> {
+        PngRef::try_from_with_policy(arr, CrcPolicy::All)
+    }
+}
+
+impl std::fmt::Display for PngDescription {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "PNG")?;
 
-        for chunk in self.chunks() {
-            writeln!(f, "Chunk {{ type:{}, length:{} }}", chunk.chunk_type().to_string(), chunk.length())?;
+        for chunk in self.chunks.iter() {
+            // `{:#}` includes the crc
+            if f.alternate() {
+                writeln!(f, "Chunk {{ type:{}, length:{}, crc:{:#010x} }}", chunk.chunk_type, chunk.length, chunk.crc)?;
+            } else {
+                writeln!(f, "Chunk {{ type:{}, length:{} }}", chunk.chunk_type, chunk.length)?;
+            }
         }
 
         Ok(())
     }
 }
 
+impl std::fmt::Display for Png {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.describe(), f)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -116,13 +823,11 @@ mod tests {
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-        chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-        chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-        chunks
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
     }
 
     fn testing_png() -> Png {
@@ -131,12 +836,7 @@ mod tests {
     }
 
     fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk> {
-        use std::str::FromStr;
-
-        let chunk_type = ChunkType::from_str(chunk_type)?;
-        let data: Vec<u8> = data.bytes().collect();
-
-        Ok(Chunk::new(chunk_type, data))
+        Chunk::from_type_and_str(chunk_type, data)
     }
 
     #[test]
@@ -205,6 +905,54 @@ mod tests {
         assert!(png.is_err());
     }
 
+    // Same chunks as `testing_png` with the crc of the given chunks corrupted
+    fn bytes_with_bad_crc(chunk_types: &[&str]) -> Vec<u8> {
+        let mut chunks = testing_chunks();
+        chunks.insert(0, chunk_from_strings("IHDR", "not really a header").unwrap());
+
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+
+        for chunk in chunks {
+            let mut chunk_bytes = chunk.as_bytes();
+
+            if chunk_types.contains(&&*chunk.chunk_type().as_str()) {
+                let last = chunk_bytes.len() - 1;
+                chunk_bytes[last] ^= 0xff;
+            }
+
+            bytes.extend(chunk_bytes);
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn test_crc_policy_all() {
+        let bytes = bytes_with_bad_crc(&["miDl"]);
+        assert!(Png::try_from_with_policy(&bytes, CrcPolicy::All).is_err());
+        assert!(Png::try_from(bytes.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_crc_policy_critical_only() {
+        let ancillary = bytes_with_bad_crc(&["miDl"]);
+        let png = Png::try_from_with_policy(&ancillary, CrcPolicy::CriticalOnly).unwrap();
+        assert!(!png.chunk_by_type("miDl").unwrap().has_valid_crc());
+
+        let critical = bytes_with_bad_crc(&["IHDR"]);
+        assert!(Png::try_from_with_policy(&critical, CrcPolicy::CriticalOnly).is_err());
+    }
+
+    #[test]
+    fn test_crc_policy_none() {
+        let ancillary = bytes_with_bad_crc(&["miDl"]);
+        assert!(Png::try_from_with_policy(&ancillary, CrcPolicy::None).is_ok());
+
+        let critical = bytes_with_bad_crc(&["IHDR"]);
+        let png = Png::try_from_with_policy(&critical, CrcPolicy::None).unwrap();
+        assert_eq!(png.as_bytes(), critical);
+    }
+
     #[test]
     fn test_list_chunks() {
         let png = testing_png();
@@ -221,14 +969,52 @@ mod tests {
     }
 
     #[test]
-    fn test_append_chunk() {
-        let mut png = testing_png();
+    fn test_chunk_by_type_mut() {
+        use std::str::FromStr;
+
+        let mut png = testing_png();
+        let chunk = png.chunk_by_type_mut("miDl").unwrap();
+        chunk.set_chunk_type(ChunkType::from_str("miDL").unwrap());
+
+        assert!(png.chunk_by_type("miDl").is_none());
+        assert!(png.chunk_by_type("miDL").unwrap().has_valid_crc());
+    }
+
+    #[test]
+    fn test_append_chunk() {
+        let mut png = testing_png();
         png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
         let chunk = png.chunk_by_type("TeSt").unwrap();
         assert_eq!(&chunk.chunk_type().to_string(), "TeSt");
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_insert_before_and_after() {
+        let mut png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("IDAT", "first").unwrap(),
+            chunk_from_strings("IDAT", "second").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ]);
+
+        assert_eq!(png.insert_before("IEND", chunk_from_strings("tEXt", "before end").unwrap()), Ok(3));
+        assert_eq!(png.insert_after("IHDR", chunk_from_strings("gAMA", "after header").unwrap()), Ok(1));
+        assert_eq!(png.insert_after("IDAT", chunk_from_strings("ruSt", "after data").unwrap()), Ok(4));
+
+        let types: Vec<String> = png.chunk_types().map(|c| c.to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "gAMA", "IDAT", "IDAT", "ruSt", "tEXt", "IEND"]);
+    }
+
+    #[test]
+    fn test_insert_missing_reference_type() {
+        let mut png = testing_png();
+
+        assert!(png.insert_before("IEND", chunk_from_strings("tEXt", "text").unwrap()).is_err());
+        assert!(png.insert_after("IHDR", chunk_from_strings("tEXt", "text").unwrap()).is_err());
+        assert_eq!(png.chunks().len(), 3);
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -238,6 +1024,215 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_remove_all_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "Another middle").unwrap());
+
+        let removed = png.remove_all_chunks("miDl");
+        let indices: Vec<usize> = removed.iter().map(|(i, _)| *i).collect();
+
+        assert_eq!(indices, vec![1, 3]);
+        assert_eq!(&removed[1].1.data_as_string().unwrap(), "Another middle");
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.chunk_by_type("miDl").is_none());
+        assert!(png.remove_all_chunks("miDl").is_empty());
+    }
+
+    #[test]
+    fn test_replace_all() {
+        let text = |msg: &str| chunk_from_strings("tEXt", msg).unwrap();
+        let types = |png: &Png| png.chunk_types().map(|c| c.to_string()).collect::<Vec<_>>();
+
+        let mut png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            text("old 1"),
+            chunk_from_strings("IDAT", "data").unwrap(),
+            text("old 2"),
+            chunk_from_strings("IEND", "").unwrap(),
+        ]);
+        png.replace_all("tEXt", vec![text("new 1"), text("new 2"), text("new 3")]);
+
+        assert_eq!(types(&png), vec!["IHDR", "tEXt", "tEXt", "tEXt", "IDAT", "IEND"]);
+        let texts: Vec<String> = png.chunks()[1..4].iter().map(|c| c.data_as_string().unwrap()).collect();
+        assert_eq!(texts, vec!["new 1", "new 2", "new 3"]);
+
+        png.replace_all("zTXt", vec![chunk_from_strings("zTXt", "z").unwrap()]);
+        assert_eq!(types(&png), vec!["IHDR", "tEXt", "tEXt", "tEXt", "IDAT", "zTXt", "IEND"]);
+
+        png.replace_all("tEXt", vec![]);
+        assert_eq!(types(&png), vec!["IHDR", "IDAT", "zTXt", "IEND"]);
+
+        let mut png = testing_png();
+        png.replace_all("miDl", vec![]);
+        png.replace_all("enDd", vec![chunk_from_strings("enDd", "end").unwrap()]);
+        assert_eq!(types(&png), vec!["FrSt", "LASt", "enDd"]);
+    }
+
+    #[test]
+    fn test_sort_ancillary_stable() {
+        let chunk = |chunk_type: &str, data: &str| chunk_from_strings(chunk_type, data).unwrap();
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", ""),
+            chunk("tEXt", "first"),
+            chunk("gAMA", ""),
+            chunk("tEXt", "second"),
+            chunk("PLTE", ""),
+            chunk("zTXt", ""),
+            chunk("bKGD", ""),
+            chunk("IDAT", ""),
+            chunk("IDAT", ""),
+            chunk("ruSt", ""),
+            chunk("iTXt", ""),
+            chunk("IEND", ""),
+        ]);
+        let critical_positions = |png: &Png| -> Vec<(usize, String)> {
+            png.chunks()
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.chunk_type().is_critical())
+                .map(|(i, c)| (i, c.chunk_type().to_string()))
+                .collect()
+        };
+        let before = critical_positions(&png);
+
+        png.sort_ancillary_stable();
+
+        let types: Vec<String> = png.chunk_types().map(|c| c.to_string()).collect();
+        assert_eq!(
+            types,
+            vec!["IHDR", "gAMA", "tEXt", "tEXt", "PLTE", "bKGD", "zTXt", "IDAT", "IDAT", "iTXt", "ruSt", "IEND"]
+        );
+        assert_eq!(critical_positions(&png), before);
+        // same types keep their order
+        assert_eq!(&png.chunks()[2].data_as_string().unwrap(), "first");
+        assert_eq!(&png.chunks()[3].data_as_string().unwrap(), "second");
+    }
+
+    #[test]
+    fn test_stream_chunks() {
+        let mut total = 0;
+        let mut types = vec![];
+
+        Png::stream_chunks(&PNG_FILE[..], |chunk| {
+            total += chunk.length();
+            types.push(chunk.chunk_type().to_string());
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+
+        assert_eq!(total, png.chunks().iter().map(Chunk::length).sum::<u32>());
+        assert_eq!(types, png.chunk_types().map(|c| c.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_stream_chunks_stops_on_break_and_errors() {
+        let mut seen = 0;
+
+        Png::stream_chunks(&PNG_FILE[..], |_| {
+            seen += 1;
+            ControlFlow::Break(())
+        })
+        .unwrap();
+
+        assert_eq!(seen, 1);
+
+        let mut corrupt = PNG_FILE.to_vec();
+        corrupt[20] ^= 1; // IHDR data
+
+        assert_eq!(Png::stream_chunks(&corrupt[..], |_| ControlFlow::Continue(())), Err("error reading chunk"));
+        assert_eq!(
+            Png::stream_chunks(&PNG_FILE[..40], |_| ControlFlow::Continue(())),
+            Err("ran out of bytes reading chunk")
+        );
+        assert_eq!(Png::stream_chunks(&PNG_FILE[..4], |_| ControlFlow::Continue(())), Err("ran out of bytes reading png header"));
+    }
+
+    #[test]
+    fn test_remove_unknown_chunks() {
+        let mut png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "").unwrap(),
+            chunk_from_strings("tEXt", "Comment\0kept").unwrap(),
+            chunk_from_strings("ruSt", "secret").unwrap(),
+            chunk_from_strings("gAMA", "").unwrap(),
+            chunk_from_strings("sCAL", "\u{1}1\x001").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ]);
+
+        let removed = png.remove_unknown_chunks();
+
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].0, 2);
+        assert_eq!(&removed[0].1.chunk_type().to_string(), "ruSt");
+
+        let types: Vec<String> = png.chunk_types().map(|c| c.to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "tEXt", "gAMA", "sCAL", "IEND"]);
+    }
+
+    #[test]
+    fn test_remove_chunks_where() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "Another middle").unwrap());
+        png.append_chunk(chunk_from_strings("miDl", "Last middle").unwrap());
+
+        let mut seen = 0;
+        let removed = png.remove_chunks_where(|chunk| {
+            chunk.chunk_type().eq_str("miDl") && { seen += 1; seen == 2 }
+        });
+
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].0, 3);
+        assert_eq!(png.chunks().len(), 4);
+        assert!(png.remove_chunks_where(|_| false).is_empty());
+    }
+
+    #[test]
+    fn test_remove_chunk_by_crc() {
+        let mut png = testing_png();
+        let other = chunk_from_strings("miDl", "Another middle").unwrap();
+        let crc = other.crc();
+        png.append_chunk(other);
+
+        let removed = png.remove_chunk_by_crc(crc, Some("miDl")).unwrap();
+        assert_eq!(&removed.data_as_string().unwrap(), "Another middle");
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.chunk_by_type("miDl").unwrap().data(), b"I am another chunk");
+
+        assert!(png.remove_chunk_by_crc(crc, None).is_err());
+
+        let first_crc = png.chunks()[0].crc();
+        assert!(png.remove_chunk_by_crc(first_crc, Some("miDl")).is_err());
+        assert!(png.remove_chunk_by_crc(first_crc, None).is_ok());
+    }
+
+    #[test]
+    fn test_retain_chunks() {
+        let mut png = testing_png();
+
+        assert_eq!(png.retain_chunks(|chunk| !chunk.chunk_type().eq_str("miDl")), 1);
+        assert_eq!(png.chunk_types().map(|c| c.to_string()).collect::<Vec<_>>(), vec!["FrSt", "LASt"]);
+        assert_eq!(png.retain_chunks(|_| true), 0);
+    }
+
+    #[test]
+    fn test_remove_chunk_at() {
+        let mut png = testing_png();
+        let chunk = png.remove_chunk_at(1).unwrap();
+
+        assert_eq!(&chunk.chunk_type().to_string(), "miDl");
+        assert_eq!(png.chunks().len(), 2);
+        assert_eq!(&png.chunks()[1].chunk_type().to_string(), "LASt");
+    }
+
+    #[test]
+    fn test_remove_chunk_at_out_of_range() {
+        let mut png = testing_png();
+        assert!(png.remove_chunk_at(3).is_err());
+        assert_eq!(png.chunks().len(), 3);
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -248,10 +1243,521 @@ mod tests {
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
         let actual = png.as_bytes();
-        let expected: Vec<u8> = PNG_FILE.iter().copied().collect();
+        let expected: Vec<u8> = PNG_FILE.to_vec();
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_describe() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+
+        let summary = |chunk_type: &str, length, crc| ChunkSummary {
+            chunk_type: chunk_type.to_string(),
+            length,
+            crc,
+            critical: chunk_type.as_bytes()[0].is_ascii_uppercase(),
+            public: chunk_type.as_bytes()[1].is_ascii_uppercase(),
+            safe_to_copy: chunk_type.as_bytes()[3].is_ascii_lowercase(),
+        };
+
+        let expected = PngDescription {
+            chunks: vec![
+                summary("IHDR", 13, 0x1e3f88b1),
+                summary("sRGB", 1, 0xaece1ce9),
+                summary("gAMA", 4, 0x0bfc6105),
+                summary("pHYs", 9, 0x15284a80),
+                summary("IDAT", 4681, 0x42b0eb10),
+                summary("RuSt", 3, 0x9eb0f5a0),
+                summary("IEND", 0, 0xae426082),
+            ],
+        };
+
+        assert_eq!(png.describe(), expected);
+        assert!(!png.describe().chunks[1].critical);
+        assert!(png.describe().chunks[5].safe_to_copy);
+    }
+
+    #[test]
+    fn test_signature_round_trip() {
+        assert!(Png::has_valid_signature(&PNG_FILE));
+
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let bytes = png.as_bytes();
+        assert_eq!(&bytes[..8], &PNG_FILE[..8]);
+        assert_eq!(&bytes[..8], &PNG_SIGNATURE);
+
+        let mut written = Vec::new();
+        png.write_to(&mut written).unwrap();
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn test_short_signature() {
+        assert!(!Png::has_valid_signature(&PNG_SIGNATURE[..7]));
+        assert!(Png::try_from(&PNG_SIGNATURE[..7]).is_err());
+        assert!(Png::try_from(&PNG_SIGNATURE[..]).is_ok());
+    }
+
+    #[test]
+    fn test_display_binary_png() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let lines: Vec<String> = png.to_string().lines().map(String::from).collect();
+
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[0], "PNG");
+        assert_eq!(lines[5], "Chunk { type:IDAT, length:4681 }");
+        assert_eq!(format!("{:#}", png).lines().nth(7).unwrap(), "Chunk { type:IEND, length:0, crc:0xae426082 }");
+    }
+
+    #[test]
+    fn test_concatenated_text() {
+        let png = testing_png();
+        assert_eq!(png.concatenated_text(), "I am the first chunkI am another chunkI am the last chunk");
+    }
+
+    #[test]
+    fn test_trailing_bytes_round_trip() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(png.trailing_bytes().is_empty());
+
+        png.set_trailing_bytes(b"hidden after the end".to_vec());
+        let bytes = png.as_bytes();
+        assert!(bytes.ends_with(b"hidden after the end"));
+
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.trailing_bytes(), b"hidden after the end");
+        assert_eq!(png.chunks().len(), 7);
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_chunks_after_iend_are_still_chunks() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend(chunk_from_strings("ruSt", "appended").unwrap().as_bytes());
+
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        assert!(png.trailing_bytes().is_empty());
+        assert_eq!(&png.chunk_by_type("ruSt").unwrap().data_as_string().unwrap(), "appended");
+    }
+
+    #[test]
+    fn test_short_tail_is_trailing() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(&[1, 2, 3, 4, 5]);
+
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.chunks().len(), 7);
+        assert_eq!(png.trailing_bytes(), &[1, 2, 3, 4, 5]);
+
+    }
+
+    #[test]
+    fn test_short_tail_without_iend_is_truncated() {
+        let mut bytes = testing_png().as_bytes();
+        let tail_offset = bytes.len();
+        bytes.extend_from_slice(&[1, 2, 3, 4, 5]);
+
+        assert_eq!(Png::try_from(bytes.as_ref()).err(), Some("ran out of bytes reading chunk"));
+        assert_eq!(
+            Png::parse_body(&bytes[PNG_SIGNATURE.len()..], CrcPolicy::All, ParseLimits::default()).err(),
+            Some(ParseFailure { offset: tail_offset, error: "ran out of bytes reading chunk" })
+        );
+
+        // a file cut off inside IEND
+        let cut = &PNG_FILE[..PNG_FILE.len() - 5];
+        assert_eq!(Png::try_from(cut).err(), Some("ran out of bytes reading chunk"));
+    }
+
+    #[test]
+    fn test_crc_errors() {
+        let bytes = bytes_with_bad_crc(&["FrSt", "LASt"]);
+        let png = Png::try_from_with_policy(&bytes, CrcPolicy::None).unwrap();
+
+        let errors: Vec<(usize, String)> = png
+            .crc_errors()
+            .into_iter()
+            .map(|(i, chunk)| (i, chunk.chunk_type().to_string()))
+            .collect();
+        assert_eq!(errors, vec![(1, "FrSt".to_string()), (3, "LASt".to_string())]);
+
+        assert!(Png::try_from(&PNG_FILE[..]).unwrap().crc_errors().is_empty());
+    }
+
+    #[test]
+    fn test_validate_signature_and_collect() {
+        assert_eq!(Png::validate_signature_and_collect(&PNG_FILE), HealthReport {
+            signature_valid: true,
+            ihdr_present: true,
+            ihdr_first: true,
+            iend_present: true,
+            iend_last: true,
+            crc_errors: 0,
+            // RuSt
+            unknown_chunks: 1,
+            trailing_bytes: 0,
+            parse_error: None,
+        });
+
+        // no IEND, a bad signature and crc
+        let mut bytes = bytes_with_bad_crc(&["miDl"]);
+        bytes[1] = b'J';
+
+        assert_eq!(Png::validate_signature_and_collect(&bytes), HealthReport {
+            signature_valid: false,
+            ihdr_present: true,
+            ihdr_first: true,
+            iend_present: false,
+            iend_last: false,
+            crc_errors: 1,
+            unknown_chunks: 3,
+            trailing_bytes: 0,
+            parse_error: None,
+        });
+
+        // without IEND a short tail means the file was cut off
+        bytes.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(Png::validate_signature_and_collect(&bytes).parse_error, Some("ran out of bytes reading chunk"));
+
+        let report = Png::validate_signature_and_collect(&bytes[..bytes.len() - 13]);
+        assert!(report.parse_error.is_some());
+        assert!(!report.ihdr_present);
+    }
+
+    #[test]
+    fn test_order_violations() {
+        assert!(Png::try_from(&PNG_FILE[..]).unwrap().order_violations().is_empty());
+
+        let png = Png::from_chunks(vec![
+            chunk_from_strings("tRNS", "").unwrap(),
+            chunk_from_strings("IHDR", "").unwrap(),
+            chunk_from_strings("PLTE", "").unwrap(),
+            chunk_from_strings("IDAT", "").unwrap(),
+            chunk_from_strings("tEXt", "").unwrap(),
+            chunk_from_strings("gAMA", "").unwrap(),
+            chunk_from_strings("IDAT", "").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+            chunk_from_strings("ruSt", "").unwrap(),
+        ]);
+
+        assert_eq!(png.order_violations(), vec![
+            (0, "must come after PLTE"),
+            (1, "must be the first chunk"),
+            (5, "must come before PLTE"),
+            (6, "IDAT chunks must be consecutive"),
+            (8, "comes after IEND"),
+        ]);
+    }
+
+    #[test]
+    fn test_byte_size() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.byte_size(), PNG_FILE.len());
+
+        png.set_trailing_bytes(vec![0; 7]);
+        assert_eq!(png.byte_size(), png.as_bytes().len());
+    }
+
+    #[test]
+    fn test_chunk_types() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let fingerprint: Vec<String> = png.chunk_types().map(|chunk_type| chunk_type.to_string()).collect();
+
+        assert_eq!(fingerprint, vec!["IHDR", "sRGB", "gAMA", "pHYs", "IDAT", "RuSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_contains_chunk_type() {
+        let png = testing_png();
+
+        assert!(png.contains_chunk_type("FrSt"));
+        assert!(png.contains_chunk_type("LASt"));
+        assert!(!png.contains_chunk_type("tEXt"));
+        assert!(!png.contains_chunk_type("frst"));
+    }
+
+    #[test]
+    fn test_append_chunk_index() {
+        let mut png = testing_png();
+
+        assert_eq!(png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap()), 3);
+        assert_eq!(png.append_chunk(chunk_from_strings("TeSt", "Again").unwrap()), 4);
+        assert_eq!(png.chunks()[4].data(), b"Again");
+    }
+
+    #[test]
+    fn test_append_chunk_checked() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+
+        assert!(png.append_chunk_checked(chunk_from_strings("IHDR", "").unwrap()).is_err());
+        assert!(png.append_chunk_checked(chunk_from_strings("IEND", "").unwrap()).is_err());
+        assert_eq!(png.chunks().len(), 7);
+
+        // repeatable chunk types are fine
+        assert_eq!(png.append_chunk_checked(chunk_from_strings("ruSt", "").unwrap()), Ok(7));
+
+        let mut png = testing_png();
+        assert_eq!(png.append_chunk_checked(chunk_from_strings("IEND", "").unwrap()), Ok(3));
+    }
+
+    #[test]
+    fn test_try_from_raw() {
+        let raw = &PNG_FILE[PNG_SIGNATURE.len()..];
+
+        assert!(Png::try_from(raw).is_err());
+
+        let png = Png::try_from_raw_with_policy(raw, CrcPolicy::All).unwrap();
+        assert_eq!(png.chunks().len(), 7);
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_truncated_chunk_is_an_error() {
+        let bytes = testing_png().as_bytes();
+        assert!(Png::try_from(&bytes[..bytes.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn test_max_chunks_limit() {
+        let empty_chunk = chunk_from_strings("emPt", "").unwrap().as_bytes();
+
+        let mut bytes = PNG_SIGNATURE.to_vec();
+        for _ in 0..1000 {
+            bytes.extend_from_slice(&empty_chunk);
+        }
+
+        let limits = |max_chunks| ParseLimits { max_chunks };
+
+        assert!(Png::try_from_with_limits(&bytes, CrcPolicy::All, limits(999)).is_err());
+        assert!(Png::try_from_with_limits(&bytes, CrcPolicy::All, limits(1000)).is_ok());
+        assert_eq!(Png::try_from(bytes.as_ref()).unwrap().chunks().len(), 1000);
+    }
+
+    #[test]
+    fn test_decompressed_image_data() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+
+        // 50 rows of a filter byte followed by 50 RGBA pixels
+        assert_eq!(png.decompressed_image_data().unwrap().len(), 50 * (1 + 50 * 4));
+    }
+
+    fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Chunk {
+        let mut data = [width.to_be_bytes(), height.to_be_bytes()].concat();
+        data.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+
+        Chunk::new(ChunkType::try_from(*b"IHDR").unwrap(), data)
+    }
+
+    fn compressed(data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_image_data_adler32() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let (stored, computed) = png.image_data_adler32().unwrap();
+        assert_eq!(stored, computed);
+        assert_eq!(computed, zlib::adler32(&png.decompressed_image_data().unwrap()));
+
+        let mut stream = compressed(b"\0some pixels");
+        let last = stream.len() - 1;
+        stream[last] ^= 0xff;
+        let idat = |data: Vec<u8>| Chunk::new(ChunkType::try_from(*b"IDAT").unwrap(), data);
+
+        let tampered = Png::from_chunks(vec![ihdr(1, 1, 8, 0, 0), idat(stream.clone())]);
+        let (stored, computed) = tampered.image_data_adler32().unwrap();
+        assert_eq!(computed, zlib::adler32(b"\0some pixels"));
+        assert_eq!(stored, computed ^ 0xff);
+        assert!(tampered.decompressed_image_data().is_err());
+
+        let truncated = Png::from_chunks(vec![idat(stream[..last].to_vec())]);
+        assert_eq!(truncated.image_data_adler32(), Err("zlib stream ends before its Adler-32"));
+        assert_eq!(testing_png().image_data_adler32(), Err("no IDAT chunks"));
+    }
+
+    #[test]
+    fn test_adler32() {
+        // the example from the Adler-32 article on Wikipedia
+        assert_eq!(zlib::adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(zlib::adler32(b""), 1);
+    }
+
+    #[test]
+    fn test_expected_image_data_len() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.expected_image_data_len(), Ok(png.decompressed_image_data().unwrap().len()));
+
+        let expected = |ihdr: Chunk| Png::from_chunks(vec![ihdr]).expected_image_data_len();
+
+        // 10 pixels of 1 bit fit in 2 bytes
+        assert_eq!(expected(ihdr(10, 3, 1, 0, 0)), Ok(3 * (1 + 2)));
+        assert_eq!(expected(ihdr(3, 2, 4, 3, 0)), Ok(2 * (1 + 2)));
+        assert_eq!(expected(ihdr(2, 2, 16, 2, 0)), Ok(2 * (1 + 12)));
+        // a 1x1 interlaced image only has pixels in the first pass
+        assert_eq!(expected(ihdr(1, 1, 8, 6, 1)), Ok(1 + 4));
+        // the seven passes of an 8x8 image hold 1, 1, 2, 4, 8, 16 and 32 pixels
+        assert_eq!(expected(ihdr(8, 8, 8, 0, 1)), Ok(2 + 2 + 3 + 2 * 3 + 2 * 5 + 4 * 5 + 4 * 9));
+        assert_eq!(expected(ihdr(1, 1, 8, 5, 0)), Err("unknown color type"));
+        assert_eq!(Png::from_chunks(vec![]).expected_image_data_len(), Err("no IHDR chunk"));
+    }
+
+    #[test]
+    fn test_truncated_image_data_len() {
+        let idat = Chunk::new(ChunkType::try_from(*b"IDAT").unwrap(), compressed(&[0; 10]));
+        let png = Png::from_chunks(vec![ihdr(2, 2, 8, 2, 0), idat]);
+
+        assert_eq!(png.expected_image_data_len(), Ok(14));
+        assert_eq!(png.decompressed_image_data().unwrap().len(), 10);
+    }
+
+    #[test]
+    fn test_corrupted_image_data() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+
+        let chunks = png
+            .chunks()
+            .iter()
+            .map(|chunk| {
+                let mut data = chunk.data().to_vec();
+
+                if chunk.chunk_type().as_str() == "IDAT" {
+                    data[100] ^= 0xff;
+                }
+
+                Chunk::new(ChunkType::try_from(chunk.chunk_type().bytes()).unwrap(), data)
+            })
+            .collect();
+
+        assert!(Png::from_chunks(chunks).decompressed_image_data().is_err());
+        assert!(testing_png().decompressed_image_data().is_err());
+    }
+
+    #[test]
+    fn test_chunks_mut() {
+        let mut png = testing_png();
+
+        let chunk = &mut png.chunks_mut()[0];
+        chunk.data_mut()[0] ^= 0xff;
+        chunk.recompute_crc();
+
+        let expected = png.chunks()[0].data().to_vec();
+        let png = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert_eq!(png.chunks()[0].data(), &expected[..]);
+    }
+
+    #[test]
+    fn test_validate_structure() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.validate_structure(), Ok(()));
+
+        png.insert_after("IHDR", chunk_from_strings("IDAT", "split").unwrap()).unwrap();
+        assert_eq!(png.validate_structure(), Err("chunks are out of order"));
+
+        let png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ]);
+        assert_eq!(png.validate_structure(), Err("missing IDAT chunk"));
+        assert_eq!(testing_png().validate_structure(), Err("IHDR must be the first chunk"));
+
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_after("pHYs", chunk_from_strings("PLTE", "").unwrap()).unwrap();
+        png.insert_after("pHYs", chunk_from_strings("PLTE", "").unwrap()).unwrap();
+        assert_eq!(png.validate_structure(), Err("duplicate IHDR, PLTE or IEND chunk"));
+    }
+
+    #[test]
+    fn test_chunk_count_by_type() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.chunk_count_by_type("IDAT"), 1);
+
+        png.insert_after("IDAT", chunk_from_strings("IDAT", "more").unwrap()).unwrap();
+        png.insert_after("IDAT", chunk_from_strings("IDAT", "and more").unwrap()).unwrap();
+        assert_eq!(png.chunk_count_by_type("IDAT"), 3);
+        assert_eq!(png.chunk_count_by_type("tEXt"), 0);
+    }
+
+    #[test]
+    fn test_to_writer_checked_refuses_missing_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.remove_chunk("IEND").unwrap();
+
+        let mut written = vec![];
+        assert_eq!(png.to_writer_checked(&mut written), Err("IEND must be the last chunk"));
+        assert!(written.is_empty());
+
+        png.append_chunk(chunk_from_strings("IEND", "").unwrap());
+        png.to_writer_checked(&mut written).unwrap();
+        assert_eq!(written, PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_check_signature() {
+        assert!(Png::check_signature(&PNG_FILE).is_ok());
+        assert_eq!(Png::check_signature(&PNG_SIGNATURE[..7]), Err("ran out of bytes reading png header"));
+        assert_eq!(Png::check_signature(b"GIF89a\0\0 not a png"), Err("invalid png header"));
+    }
+
+    #[test]
+    fn test_parse_body_failure_offsets() {
+        let mut body = b"\0\0\0\x01????xcrc!".to_vec();
+        let failure = Png::parse_body(&body, CrcPolicy::All, ParseLimits::default()).err().unwrap();
+        assert_eq!(failure, ParseFailure { offset: 8, error: "error reading chunk" });
+
+        let first = chunk_from_strings("FrSt", "I am the first chunk").unwrap();
+        body.splice(0..0, first.as_bytes());
+
+        let failure = Png::parse_body(&body, CrcPolicy::All, ParseLimits::default()).err().unwrap();
+        assert_eq!(failure.offset, 8 + first.total_len());
+
+        let failure = Png::parse_raw_body(&body, CrcPolicy::All, ParseLimits::default()).err().unwrap();
+        assert_eq!(failure.offset, first.total_len());
+    }
+
+    #[test]
+    fn test_png_ref_matches_owned_parse() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"trailing");
+
+        let owned = Png::try_from(&bytes[..]).unwrap();
+        let borrowed = PngRef::try_from(&bytes[..]).unwrap();
+
+        assert_eq!(borrowed.chunks().len(), owned.chunks().len());
+
+        for (chunk_ref, chunk) in borrowed.chunks().iter().zip(owned.chunks()) {
+            assert_eq!(chunk_ref.chunk_type(), chunk.chunk_type());
+            assert_eq!(chunk_ref.length(), chunk.length());
+            assert_eq!(chunk_ref.data(), chunk.data());
+            assert_eq!(chunk_ref.crc(), chunk.crc());
+            assert_eq!(&chunk_ref.to_chunk(), chunk);
+        }
+
+        assert_eq!(borrowed.trailing_bytes(), owned.trailing_bytes());
+        assert_eq!(borrowed.byte_size(), owned.byte_size());
+        assert_eq!(borrowed.to_png().as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_png_ref_borrows_input() {
+        let bytes = testing_png().as_bytes();
+        let borrowed = PngRef::try_from(&bytes[..]).unwrap();
+
+        let data = borrowed.chunk_by_type("miDl").unwrap().data();
+        assert!(bytes.as_ptr_range().contains(&data.as_ptr()));
+        assert_eq!(data, b"I am another chunk");
+    }
+
+    #[test]
+    fn test_png_ref_crc_policy() {
+        let bytes = bytes_with_bad_crc(&["miDl"]);
+
+        assert!(PngRef::try_from(&bytes[..]).is_err());
+        assert!(PngRef::try_from_with_policy(&bytes[..], CrcPolicy::CriticalOnly).is_ok());
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
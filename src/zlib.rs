@@ -0,0 +1,53 @@
+use std::io::Read;
+
+use crate::Result;
+
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = flate2::read::ZlibDecoder::new(data);
+    let mut buffer = Vec::new();
+
+    match decoder.read_to_end(&mut buffer) {
+        Ok(_) => Ok(buffer),
+        Err(_) => Err("error decompressing zlib stream"),
+    }
+}
+
+/*
+The Adler-32 stored after the deflate data of a zlib stream, and the one
+computed over what it inflates to. The deflate data is read on its own so a
+mismatch can be reported instead of failing like `inflate` does
+*/
+pub fn adler32_check(stream: &[u8]) -> Result<(u32, u32)> {
+    // the 2 byte header, the compressed data, then the 4 byte Adler-32
+    if stream.len() < 6 {
+        return Err("zlib stream is too short");
+    }
+
+    let mut decoder = flate2::read::DeflateDecoder::new(&stream[2..]);
+    let mut buffer = Vec::new();
+
+    if decoder.read_to_end(&mut buffer).is_err() {
+        return Err("error decompressing zlib stream");
+    }
+
+    let end = 2 + decoder.total_in() as usize;
+    let stored = match stream.get(end..end + 4) {
+        Some(b) => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        None => return Err("zlib stream ends before its Adler-32"),
+    };
+
+    Ok((stored, adler32(&buffer)))
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for &byte in data {
+        a = (a + byte as u32) % MOD_ADLER;
+        b = (b + a) % MOD_ADLER;
+    }
+
+    (b << 16) | a
+}